@@ -0,0 +1,51 @@
+//! Times every script in `benches/scripts` on each backend. Run it with and
+//! without `--features nan-boxing` to compare the VM's value representations:
+//!
+//! ```text
+//! cargo bench
+//! cargo bench --features nan-boxing
+//! ```
+
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+const RUNS: usize = 5;
+
+/// The median wall-clock time of running `script` on `backend`.
+fn time(script: &Path, backend: &str) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let output = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+                .args(["--backend", backend])
+                .arg(script)
+                .output()
+                .unwrap();
+            let elapsed = start.elapsed();
+            assert!(output.status.success(), "{} failed on the {} backend", script.display(), backend);
+            elapsed
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/scripts");
+    let mut scripts: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    scripts.sort();
+
+    let values = if cfg!(feature = "nan-boxing") { "nan-boxed" } else { "enum" };
+    println!("vm values: {}", values);
+    println!("{:<16} {:>12} {:>12}", "script", "tree", "vm");
+    for script in scripts {
+        let name = script.file_stem().unwrap().to_string_lossy().to_string();
+        let tree = time(&script, "tree");
+        let vm = time(&script, "vm");
+        println!("{:<16} {:>10.1}ms {:>10.1}ms", name, tree.as_secs_f64() * 1000.0, vm.as_secs_f64() * 1000.0);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    collector::{address, Collector, Object, Trace},
+    expressions::expressions::{Callable, ExpressionResult, Function},
+    interner::Symbol,
+    interpreter::EvaluationError,
+};
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: Symbol,
+    superclass: Option<Rc<Class>>,
+    methods: HashMap<Symbol, Function>,
+    // tracks the instances, which can be part of cycles. Classes the host
+    // builds have no methods to form one, so they go without.
+    collector: Option<Rc<RefCell<Collector>>>,
+}
+
+impl Class {
+    pub fn new(name: Symbol, superclass: Option<Rc<Class>>, methods: HashMap<Symbol, Function>) -> Self {
+        Self { name, superclass, methods, collector: None }
+    }
+
+    /// A class whose instances are tracked by `collector`.
+    pub fn collected(name: Symbol, superclass: Option<Rc<Class>>, methods: HashMap<Symbol, Function>, collector: Rc<RefCell<Collector>>) -> Self {
+        Self { name, superclass, methods, collector: Some(collector) }
+    }
+
+    /// Looks the method up on this class, then on each superclass in turn.
+    pub fn find_method(&self, name: Symbol) -> Option<&Function> {
+        match self.methods.get(&name) {
+            Some(method) => Some(method),
+            None => match &self.superclass {
+                Some(superclass) => superclass.find_method(name),
+                None => None,
+            },
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self.find_method(Symbol::intern("init")) {
+            Some(initializer) => initializer.arity(),
+            None => 0,
+        }
+    }
+
+    /// Calling a class creates a new instance and runs its `init` method, if any.
+    pub fn instantiate(class: &Rc<Class>, args: Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError> {
+        let instance = Rc::new(RefCell::new(Instance::new(class.clone())));
+        if let Some(collector) = &class.collector {
+            Collector::track(collector, Object::Instance(instance.clone()));
+        }
+        if let Some(initializer) = class.find_method(Symbol::intern("init")) {
+            initializer.bind(instance.clone()).call(args)?;
+        }
+        Ok(ExpressionResult::Instance(instance))
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    fields: HashMap<Symbol, ExpressionResult>,
+    // what a list from the host holds, by position. These aren't fields,
+    // since a symbol per index would never be freed.
+    elements: Vec<ExpressionResult>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self { class, fields: HashMap::new(), elements: vec![] }
+    }
+
+    /// Fields shadow methods; methods come back bound to `instance`.
+    pub fn get(instance: &Rc<RefCell<Instance>>, name: Symbol) -> Result<ExpressionResult, EvaluationError> {
+        if let Some(value) = instance.borrow().fields.get(&name) {
+            return Ok(value.clone());
+        }
+
+        let class = instance.borrow().class.clone();
+        match class.find_method(name) {
+            Some(method) => Ok(ExpressionResult::Callable(Callable::UserDefined(method.bind(instance.clone())))),
+            None => Err(EvaluationError::runtime_error(format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    pub fn set(&mut self, name: Symbol, value: ExpressionResult) {
+        self.fields.insert(name, value);
+    }
+
+    /// A field, ignoring methods.
+    pub fn field(&self, name: Symbol) -> Option<ExpressionResult> {
+        self.fields.get(&name).cloned()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&Symbol, &ExpressionResult)> {
+        self.fields.iter()
+    }
+
+    /// The elements of a list made by the host. Lox code only sees its
+    /// `length` field.
+    pub fn elements(&self) -> &[ExpressionResult] {
+        &self.elements
+    }
+
+    pub fn set_elements(&mut self, elements: Vec<ExpressionResult>) {
+        self.elements = elements;
+    }
+
+    pub(crate) fn take_contents(&mut self) -> (HashMap<Symbol, ExpressionResult>, Vec<ExpressionResult>) {
+        (std::mem::take(&mut self.fields), std::mem::take(&mut self.elements))
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, refs: &mut Vec<usize>) {
+        if let Some(superclass) = &self.superclass {
+            refs.push(address(superclass));
+        }
+        for method in self.methods.values() {
+            method.trace(refs);
+        }
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, refs: &mut Vec<usize>) {
+        refs.push(address(&self.class));
+        for value in self.fields.values().chain(&self.elements) {
+            value.trace(refs);
+        }
+    }
+}
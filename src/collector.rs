@@ -0,0 +1,306 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    class::{Class, Instance},
+    environment::{Globals, Scope},
+    vm::heap::{GcConfig, GcStats},
+};
+
+/// Something the tree-walker shares through `Rc` that can end up in a
+/// reference cycle, e.g. a scope holding a closure that captured it.
+pub enum Object {
+    Scope(Rc<RefCell<Scope>>),
+    Instance(Rc<RefCell<Instance>>),
+    Class(Rc<Class>),
+    Globals(Rc<RefCell<Globals>>),
+}
+
+/// Lists the objects a value refers to, by `address`.
+pub trait Trace {
+    fn trace(&self, refs: &mut Vec<usize>);
+}
+
+/// Identifies the object behind `rc`, for tracing.
+pub fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+// what the collector keeps of an object, so it doesn't keep it alive
+enum Tracked {
+    Scope(Weak<RefCell<Scope>>),
+    Instance(Weak<RefCell<Instance>>),
+    Class(Weak<Class>),
+    Globals(Weak<RefCell<Globals>>),
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::Scope(scope) => scope.upgrade().map(Object::Scope),
+            Tracked::Instance(instance) => instance.upgrade().map(Object::Instance),
+            Tracked::Class(class) => class.upgrade().map(Object::Class),
+            Tracked::Globals(globals) => globals.upgrade().map(Object::Globals),
+        }
+    }
+}
+
+impl Object {
+    fn downgrade(&self) -> Tracked {
+        match self {
+            Object::Scope(scope) => Tracked::Scope(Rc::downgrade(scope)),
+            Object::Instance(instance) => Tracked::Instance(Rc::downgrade(instance)),
+            Object::Class(class) => Tracked::Class(Rc::downgrade(class)),
+            Object::Globals(globals) => Tracked::Globals(Rc::downgrade(globals)),
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Object::Scope(scope) => address(scope),
+            Object::Instance(instance) => address(instance),
+            Object::Class(class) => address(class),
+            Object::Globals(globals) => address(globals),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Scope(scope) => Rc::strong_count(scope),
+            Object::Instance(instance) => Rc::strong_count(instance),
+            Object::Class(class) => Rc::strong_count(class),
+            Object::Globals(globals) => Rc::strong_count(globals),
+        }
+    }
+
+    // roughly what the object costs, for deciding when to collect
+    fn size(&self) -> usize {
+        match self {
+            Object::Scope(_) => std::mem::size_of::<Scope>(),
+            Object::Instance(_) => std::mem::size_of::<Instance>(),
+            Object::Class(_) => std::mem::size_of::<Class>(),
+            Object::Globals(_) => std::mem::size_of::<Globals>(),
+        }
+    }
+
+    /// Lists what the object refers to, or returns false if it is being
+    /// changed right now and can't be looked at.
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        match self {
+            Object::Scope(scope) => match scope.try_borrow() {
+                Ok(scope) => scope.trace(refs),
+                Err(_) => return false,
+            },
+            Object::Instance(instance) => match instance.try_borrow() {
+                Ok(instance) => instance.trace(refs),
+                Err(_) => return false,
+            },
+            Object::Class(class) => class.trace(refs),
+            Object::Globals(globals) => match globals.try_borrow() {
+                Ok(globals) => globals.values().for_each(|value| value.trace(refs)),
+                Err(_) => return false,
+            },
+        }
+        true
+    }
+
+    /// Drops everything the object refers to, which breaks the cycles it is
+    /// part of. Classes can't be changed once created, so any cycle through
+    /// one also runs through a scope or instance.
+    fn clear(&self) {
+        match self {
+            Object::Scope(scope) => {
+                let (values, parent) = {
+                    let mut scope = scope.borrow_mut();
+                    (std::mem::take(&mut scope.values), scope.parent.take())
+                };
+                drop((values, parent));
+            }
+            Object::Instance(instance) => {
+                let contents = instance.borrow_mut().take_contents();
+                drop(contents);
+            }
+            Object::Class(_) => {}
+            Object::Globals(globals) => {
+                let values = std::mem::take(&mut *globals.borrow_mut());
+                drop(values);
+            }
+        }
+    }
+}
+
+/// Frees the tree-walker's objects that reference counting can't: those
+/// only kept alive by cycles. Every scope, instance, class and set of
+/// globals is tracked when it is created. A collection counts how many
+/// references to each object come from other tracked objects; any object
+/// with more references than that is held from outside, by the Rust stack
+/// or the host, and is a root. Whatever the roots can't reach is garbage.
+pub struct Collector {
+    objects: Vec<(Tracked, usize)>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+    stats: GcStats,
+}
+
+impl std::fmt::Debug for Collector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Collector({} objects)", self.objects.len())
+    }
+}
+
+impl Collector {
+    pub fn new(config: GcConfig) -> Collector {
+        Collector { objects: vec![], bytes_allocated: 0, next_gc: config.initial_threshold, config, stats: GcStats::default() }
+    }
+
+    pub fn config(&self) -> GcConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Starts tracking `object`, collecting first if enough has been
+    /// allocated since the last collection. `object` itself is kept alive
+    /// by the caller, so it always survives.
+    pub fn track(collector: &RefCell<Collector>, object: Object) {
+        let due = {
+            let mut collector = collector.borrow_mut();
+            let size = object.size();
+            collector.bytes_allocated += size;
+            collector.objects.push((object.downgrade(), size));
+            collector.config.stress || collector.bytes_allocated > collector.next_gc
+        };
+        if due {
+            Collector::collect(collector);
+        }
+    }
+
+    /// Frees every tracked object that is only reachable through cycles.
+    /// Returns how many objects were freed.
+    pub fn collect(collector: &RefCell<Collector>) -> usize {
+        let garbage = collector.borrow_mut().find_garbage();
+        let freed = garbage.len();
+        // clearing drops values, which can run arbitrary drops, so the
+        // collector must not be borrowed while it happens
+        for object in &garbage {
+            object.clear();
+        }
+        drop(garbage);
+        collector.borrow_mut().stats.objects_freed += freed;
+        freed
+    }
+
+    fn find_garbage(&mut self) -> Vec<Object> {
+        // objects freed by reference counting are simply forgotten
+        let mut objects = vec![];
+        let mut tracked = vec![];
+        for (object, size) in self.objects.drain(..) {
+            if let Some(live) = object.upgrade() {
+                objects.push((live, size));
+                tracked.push((object, size));
+            }
+        }
+        let index: HashMap<usize, usize> = objects.iter().enumerate().map(|(i, (object, _))| (object.address(), i)).collect();
+
+        // references from one tracked object to another
+        let mut internal = vec![0; objects.len()];
+        let mut edges: Vec<Vec<usize>> = vec![vec![]; objects.len()];
+        let mut roots = vec![];
+        for (i, (object, _)) in objects.iter().enumerate() {
+            let mut refs = vec![];
+            if !object.trace(&mut refs) {
+                roots.push(i);
+                continue;
+            }
+            for address in refs {
+                if let Some(&j) = index.get(&address) {
+                    internal[j] += 1;
+                    edges[i].push(j);
+                }
+            }
+        }
+        // `objects` itself holds one reference to each
+        for (i, (object, _)) in objects.iter().enumerate() {
+            if object.strong_count() - 1 > internal[i] {
+                roots.push(i);
+            }
+        }
+
+        let mut marked = vec![false; objects.len()];
+        while let Some(i) = roots.pop() {
+            if !marked[i] {
+                marked[i] = true;
+                roots.extend(edges[i].iter().copied());
+            }
+        }
+
+        let mut garbage = vec![];
+        self.bytes_allocated = 0;
+        for (((object, size), tracked), marked) in objects.into_iter().zip(tracked).zip(marked) {
+            if marked {
+                self.bytes_allocated += size;
+                self.objects.push(tracked);
+            } else {
+                garbage.push(object);
+            }
+        }
+        self.next_gc = (self.bytes_allocated * self.config.growth_factor).max(self.config.initial_threshold);
+        self.stats.collections += 1;
+        garbage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{expressions::expressions::{Callable, ExpressionResult}, parser::Literal, Interpreter};
+
+    use super::*;
+
+    // collects only when asked to
+    fn interpreter() -> Interpreter {
+        Interpreter::with_gc(GcConfig { initial_threshold: usize::MAX, ..GcConfig::default() })
+    }
+
+    #[test]
+    fn it_frees_closures_that_captured_their_own_scope() {
+        let mut interpreter = interpreter();
+        interpreter.eval("{ fun f() { return f; } }").unwrap();
+        assert_eq!(interpreter.collect_garbage(), 1);
+
+        // the scope, and the class whose methods close over it
+        interpreter.eval("{ class A { m() {} } }").unwrap();
+        assert_eq!(interpreter.collect_garbage(), 2);
+
+        interpreter.eval("class B {} var b = B(); b.self = b; b = nil;").unwrap();
+        assert_eq!(interpreter.collect_garbage(), 1);
+    }
+
+    #[test]
+    fn it_keeps_what_globals_and_the_host_still_use() {
+        let mut interpreter = interpreter();
+        let function = interpreter.eval("var keep; { fun f() { return 1; } keep = f; } fun make() { fun g() { return 2; } return g; } make();").unwrap();
+        assert_eq!(interpreter.collect_garbage(), 0);
+
+        assert_eq!(interpreter.eval("keep();").unwrap(), ExpressionResult::Literal(Literal::Number(1.0)));
+        match function {
+            ExpressionResult::Callable(callable @ Callable::UserDefined(_)) => {
+                assert_eq!(callable.call(vec![]).unwrap(), ExpressionResult::Literal(Literal::Number(2.0)));
+            }
+            other => panic!("Expected a function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_collects_as_the_program_allocates() {
+        let mut interpreter = Interpreter::with_gc(GcConfig { initial_threshold: 4096, ..GcConfig::default() });
+        interpreter.eval("for (var i = 0; i < 1000; i = i + 1) { fun f() { return i; } }").unwrap();
+        assert!(interpreter.gc_stats().collections > 0);
+        assert!(interpreter.gc_stats().objects_freed > 900);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use crate::{
+    class::{Class, Instance},
+    expressions::expressions::{ExpressionResult, NativeFn},
+    interner::Symbol,
+    interpreter::EvaluationError,
+    parser::Literal,
+};
+
+/// Converts a Rust value into a Lox value.
+///
+/// Lox has no collections, so sequences become instances of a `List` class
+/// that holds the elements but only shows Lox its `length` field, and maps
+/// become instances of a `Map` class with one field per key.
+pub trait IntoLox {
+    fn into_lox(self) -> ExpressionResult;
+}
+
+/// Converts a Lox value back into a Rust value, failing with a runtime error
+/// that names the expected type.
+pub trait FromLox: Sized {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError>;
+}
+
+fn mismatch(expected: &str, value: &ExpressionResult) -> EvaluationError {
+    EvaluationError::runtime_error(format!("Expected {}, got {}", expected, value.type_name()))
+}
+
+impl IntoLox for ExpressionResult {
+    fn into_lox(self) -> ExpressionResult {
+        self
+    }
+}
+
+impl FromLox for ExpressionResult {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> ExpressionResult {
+        ExpressionResult::Literal(Literal::Nil)
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> ExpressionResult {
+        ExpressionResult::Literal(Literal::Number(self))
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+        match value {
+            ExpressionResult::Literal(Literal::Number(n)) => Ok(*n),
+            _ => Err(mismatch("number", value)),
+        }
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($t:ty),*) => {$(
+        impl IntoLox for $t {
+            fn into_lox(self) -> ExpressionResult {
+                ExpressionResult::Literal(Literal::Number(self as f64))
+            }
+        }
+
+        impl FromLox for $t {
+            fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+                let n = f64::from_lox(value)?;
+                // `as` would silently truncate and saturate instead. The upper
+                // bound is a power of two because `MAX as f64` rounds up to one
+                // for the 64-bit types.
+                let signed = (<$t>::MIN != 0) as i32;
+                if n.fract() != 0.0 || n < <$t>::MIN as f64 || n >= 2f64.powi(<$t>::BITS as i32 - signed) {
+                    return Err(EvaluationError::runtime_error(format!("Expected {}, got {}", stringify!($t), n)));
+                }
+                Ok(n as $t)
+            }
+        }
+    )*};
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLox for bool {
+    fn into_lox(self) -> ExpressionResult {
+        ExpressionResult::Literal(Literal::Boolean(self))
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+        match value {
+            ExpressionResult::Literal(Literal::Boolean(b)) => Ok(*b),
+            _ => Err(mismatch("boolean", value)),
+        }
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> ExpressionResult {
+        ExpressionResult::Literal(Literal::String(self.into()))
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self) -> ExpressionResult {
+        self.as_str().into_lox()
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+        match value {
+            ExpressionResult::Literal(Literal::String(s)) => Ok(s.to_string()),
+            _ => Err(mismatch("string", value)),
+        }
+    }
+}
+
+/// `None` is nil, and nil is `None`.
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> ExpressionResult {
+        match self {
+            Some(value) => value.into_lox(),
+            None => ExpressionResult::Literal(Literal::Nil),
+        }
+    }
+}
+
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+        match value {
+            ExpressionResult::Literal(Literal::Nil) => Ok(None),
+            _ => T::from_lox(value).map(Some),
+        }
+    }
+}
+
+fn instance_of(class_name: &str, fields: impl IntoIterator<Item = (Symbol, ExpressionResult)>) -> Instance {
+    let class = Rc::new(Class::new(Symbol::intern(class_name), None, HashMap::new()));
+    let mut instance = Instance::new(class);
+    for (name, value) in fields {
+        instance.set(name, value);
+    }
+    instance
+}
+
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self) -> ExpressionResult {
+        let mut list = instance_of("List", [(Symbol::intern("length"), self.len().into_lox())]);
+        list.set_elements(self.into_iter().map(IntoLox::into_lox).collect());
+        ExpressionResult::Instance(Rc::new(RefCell::new(list)))
+    }
+}
+
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+        let instance = match value {
+            ExpressionResult::Instance(instance) => instance.borrow(),
+            _ => return Err(mismatch("list", value)),
+        };
+        let length = match instance.field(Symbol::intern("length")) {
+            Some(length) => usize::from_lox(&length)?,
+            None => return Err(mismatch("list", value)),
+        };
+        (0..length)
+            .map(|i| match instance.elements().get(i) {
+                Some(element) => T::from_lox(element),
+                None => Err(EvaluationError::runtime_error(format!("List is missing element {}", i))),
+            })
+            .collect()
+    }
+}
+
+macro_rules! map_conversions {
+    ($($map:ident),*) => {$(
+        impl<T: IntoLox> IntoLox for $map<String, T> {
+            fn into_lox(self) -> ExpressionResult {
+                let map = instance_of("Map", self.into_iter().map(|(key, value)| (Symbol::intern(&key), value.into_lox())));
+                ExpressionResult::Instance(Rc::new(RefCell::new(map)))
+            }
+        }
+
+        impl<T: FromLox> FromLox for $map<String, T> {
+            fn from_lox(value: &ExpressionResult) -> Result<Self, EvaluationError> {
+                match value {
+                    ExpressionResult::Instance(instance) => instance
+                        .borrow()
+                        .fields()
+                        .map(|(key, value)| Ok((key.to_string(), T::from_lox(value)?)))
+                        .collect(),
+                    _ => Err(mismatch("map", value)),
+                }
+            }
+        }
+    )*};
+}
+
+map_conversions!(HashMap, BTreeMap);
+
+/// What a typed native function may return: any value, or a `Result` when
+/// it can fail.
+pub trait IntoNativeResult {
+    fn into_native_result(self) -> Result<ExpressionResult, EvaluationError>;
+}
+
+impl<T: IntoLox> IntoNativeResult for T {
+    fn into_native_result(self) -> Result<ExpressionResult, EvaluationError> {
+        Ok(self.into_lox())
+    }
+}
+
+impl<T: IntoLox> IntoNativeResult for Result<T, EvaluationError> {
+    fn into_native_result(self) -> Result<ExpressionResult, EvaluationError> {
+        self.map(IntoLox::into_lox)
+    }
+}
+
+/// A Rust closure whose parameters and result convert to and from Lox,
+/// registered with `Interpreter::define_function`. `Args` is the tuple of
+/// its parameter types, which is what lets closures of every arity
+/// implement it.
+pub trait TypedNative<Args> {
+    fn arity(&self) -> usize;
+    fn into_native(self, name: Symbol) -> Rc<NativeFn>;
+}
+
+fn argument<T: FromLox>(name: Symbol, args: &[ExpressionResult], index: usize) -> Result<T, EvaluationError> {
+    T::from_lox(&args[index]).map_err(|e| {
+        EvaluationError::runtime_error(format!("Argument {} of '{}': {}", index + 1, name, e.message))
+    })
+}
+
+macro_rules! typed_native {
+    ($arity:expr; $($arg:ident: $index:tt),*) => {
+        impl<F, R, $($arg),*> TypedNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoNativeResult,
+            $($arg: FromLox),*
+        {
+            fn arity(&self) -> usize {
+                $arity
+            }
+
+            #[allow(unused_variables)]
+            fn into_native(self, name: Symbol) -> Rc<NativeFn> {
+                Rc::new(move |args| self($(argument::<$arg>(name, &args, $index)?),*).into_native_result())
+            }
+        }
+    };
+}
+
+typed_native!(0;);
+typed_native!(1; A: 0);
+typed_native!(2; A: 0, B: 1);
+typed_native!(3; A: 0, B: 1, C: 2);
+typed_native!(4; A: 0, B: 1, C: 2, D: 3);
+typed_native!(5; A: 0, B: 1, C: 2, D: 3, E: 4);
+typed_native!(6; A: 0, B: 1, C: 2, D: 3, E: 4, G: 5);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: IntoLox + FromLox>(value: T) -> T {
+        T::from_lox(&value.into_lox()).unwrap()
+    }
+
+    #[test]
+    fn it_round_trips_scalars() {
+        assert_eq!(round_trip(1.5), 1.5);
+        assert_eq!(round_trip(-3i64), -3);
+        assert!(round_trip(true));
+        assert_eq!(round_trip("hi".to_string()), "hi");
+        assert_eq!(round_trip(Some(2u8)), Some(2));
+        assert_eq!(round_trip(None::<f64>), None);
+    }
+
+    #[test]
+    fn it_round_trips_collections() {
+        assert_eq!(round_trip(vec![1, 2, 3]), vec![1, 2, 3]);
+        assert_eq!(round_trip(Vec::<bool>::new()), vec![]);
+
+        let map = BTreeMap::from([("a".to_string(), 1.0), ("b".to_string(), 2.0)]);
+        assert_eq!(round_trip(map.clone()), map);
+    }
+
+    #[test]
+    fn it_rejects_mismatched_types() {
+        let error = f64::from_lox(&"one".into_lox()).unwrap_err();
+        assert_eq!(error.message, "Expected number, got string");
+
+        let error = u8::from_lox(&300.into_lox()).unwrap_err();
+        assert_eq!(error.message, "Expected u8, got 300");
+        assert!(i32::from_lox(&1.5.into_lox()).is_err());
+    }
+
+    #[test]
+    fn it_rejects_numbers_just_past_the_widest_integers() {
+        assert!(i64::from_lox(&2f64.powi(63).into_lox()).is_err());
+        assert!(u64::from_lox(&2f64.powi(64).into_lox()).is_err());
+        assert_eq!(i64::from_lox(&(-2f64).powi(63).into_lox()).unwrap(), i64::MIN);
+        assert_eq!(u8::from_lox(&255.into_lox()).unwrap(), 255);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{
+    interpreter::{ErrorType, EvaluationError},
+    parser::ParseError,
+    resolver::ResolveError,
+    scanner::ScanError,
+    tokens::{Span, TokenType},
+    vm::compiler::CompileError,
+};
+
+pub const UNEXPECTED_CHARACTER: &str = "E0001";
+pub const UNTERMINATED_STRING: &str = "E0002";
+pub const UNTERMINATED_COMMENT: &str = "E0003";
+pub const SYNTAX_ERROR: &str = "E0100";
+pub const RESOLVE_ERROR: &str = "E0200";
+pub const RUNTIME_ERROR: &str = "E0300";
+pub const LIMIT_EXCEEDED: &str = "E0301";
+pub const COMPILE_ERROR: &str = "E0400";
+
+/// An error ready to be shown to the user: what went wrong, where, and any
+/// extra hints. Every phase converts its own error type into one of these so
+/// they all render the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, message: String, span: Option<Span>) -> Self {
+        Self { code, message, span, notes: vec![] }
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Renders the diagnostic against the source it came from, e.g.
+    ///
+    /// ```text
+    /// error[E0100]: Expect ';' after value.
+    ///  --> script.lox:2:1
+    ///   |
+    /// 2 | var = 2;
+    ///   | ^^^
+    /// ```
+    pub fn render(&self, source_name: &str, source: &str) -> String {
+        let mut out = format!("error[{}]: {}\n", self.code, self.message);
+
+        if let Some(span) = self.span {
+            let line_text = source.lines().nth(span.line - 1).unwrap_or("");
+            let gutter = " ".repeat(span.line.to_string().len());
+
+            // underline up to the end of the span or its first line, whichever
+            // comes first, and always at least one character
+            let line_end = line_text.chars().count() + 1;
+            let length = match source.get(span.start..span.end) {
+                Some(text) => text.chars().count(),
+                None => span.end.saturating_sub(span.start),
+            };
+            let width = length.min(line_end.saturating_sub(span.column)).max(1);
+
+            out.push_str(&format!("{}--> {}:{}:{}\n", gutter, source_name, span.line, span.column));
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", span.line, line_text));
+            // tabs are copied, so the carets line up however wide they show
+            let mut padding: String = line_text.chars().take(span.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            padding.push_str(&" ".repeat((span.column - 1).saturating_sub(padding.chars().count())));
+            out.push_str(&format!("{} | {}{}\n", gutter, padding, "^".repeat(width)));
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("  = note: {}\n", note));
+        }
+        out
+    }
+}
+
+impl From<&ScanError> for Diagnostic {
+    fn from(error: &ScanError) -> Self {
+        Diagnostic::new(error.code, error.message.clone(), Some(error.span))
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let diagnostic = Diagnostic::new(SYNTAX_ERROR, error.message.clone(), Some(error.token.span));
+        if error.token.token_type == TokenType::EOF {
+            return diagnostic.with_note("reached the end of the input".to_string());
+        }
+        diagnostic.with_note(format!("found '{}'", error.token.lexeme))
+    }
+}
+
+impl From<&ResolveError> for Diagnostic {
+    fn from(error: &ResolveError) -> Self {
+        Diagnostic::new(RESOLVE_ERROR, error.message.clone(), Some(error.span))
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        Diagnostic::new(COMPILE_ERROR, error.message.clone(), Some(error.span))
+    }
+}
+
+// the most backtrace lines one error prints
+const MAX_TRACE_NOTES: usize = 20;
+
+impl From<&EvaluationError> for Diagnostic {
+    fn from(error: &EvaluationError) -> Self {
+        let code = match error.kind {
+            ErrorType::StepLimitExceeded | ErrorType::CallDepthExceeded | ErrorType::Timeout => LIMIT_EXCEEDED,
+            ErrorType::RuntimeError | ErrorType::BreakError => RUNTIME_ERROR,
+        };
+        let mut diagnostic = Diagnostic::new(code, error.message.clone(), error.span);
+        // errors raised at the top level have nothing worth tracing
+        if error.trace.is_empty() {
+            return diagnostic;
+        }
+        let mut notes = vec![];
+        let backtrace = error.backtrace();
+        let mut frames = backtrace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let (function, location) = frame;
+            notes.push(match location {
+                Some(span) => format!("in {} at line {}, column {}", function, span.line, span.column),
+                None => format!("in {}", function),
+            });
+            // deep recursion repeats the same frame over and over
+            let mut repeats = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                notes.push(format!("... {} more {} to {}", repeats, if repeats == 1 { "call" } else { "calls" }, function));
+            }
+        }
+        // like jlox and clox, keep the frames nearest the error and the script
+        if notes.len() > MAX_TRACE_NOTES {
+            let hidden = notes.len() - MAX_TRACE_NOTES;
+            notes.splice(MAX_TRACE_NOTES / 2..MAX_TRACE_NOTES / 2 + hidden, [format!("... {} more frames", hidden)]);
+        }
+        for note in notes {
+            diagnostic = diagnostic.with_note(note);
+        }
+        diagnostic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_underlines_the_span() {
+        let source = "var a = 1;\nprint a - \"b\";\n";
+        let diagnostic = Diagnostic::new(RUNTIME_ERROR, "Expected two numbers".to_string(), Some(Span::new(17, 24, 2, 7)));
+        assert_eq!(
+            diagnostic.render("test.lox", source),
+            "error[E0300]: Expected two numbers\n \
+             --> test.lox:2:7\n  \
+             |\n\
+             2 | print a - \"b\";\n  \
+             |       ^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn it_renders_notes() {
+        let diagnostic = Diagnostic::new(SYNTAX_ERROR, "Expect expression.".to_string(), Some(Span::new(4, 5, 1, 5)))
+            .with_note("found ';'".to_string());
+        assert_eq!(
+            diagnostic.render("test.lox", "1 + ;"),
+            "error[E0100]: Expect expression.\n \
+             --> test.lox:1:5\n  \
+             |\n\
+             1 | 1 + ;\n  \
+             |     ^\n  \
+             = note: found ';'\n"
+        );
+    }
+
+    #[test]
+    fn it_clips_multi_line_spans_to_the_first_line() {
+        let diagnostic = Diagnostic::new(UNTERMINATED_STRING, "Unterminated string.".to_string(), Some(Span::new(6, 14, 1, 7)));
+        let rendered = diagnostic.render("test.lox", "print \"abc\ndef");
+        assert!(rendered.ends_with("1 | print \"abc\n  |       ^^^^\n"));
+    }
+
+    #[test]
+    fn it_keeps_tabs_in_the_padding_under_the_line() {
+        let diagnostic = Diagnostic::new(RUNTIME_ERROR, "Expected number".to_string(), Some(Span::new(8, 10, 1, 9)));
+        let rendered = diagnostic.render("test.lox", "\t\tprint -x;");
+        assert!(rendered.ends_with("1 | \t\tprint -x;\n  | \t\t      ^^\n"), "{:?}", rendered);
+    }
+
+    #[test]
+    fn it_renders_runtime_backtraces_innermost_first() {
+        let error = EvaluationError::runtime_error("Undefined variable 'x'".to_string())
+            .with_span(Span::new(30, 31, 3, 12))
+            .in_call("inner".to_string(), Span::new(50, 57, 6, 5))
+            .in_call("outer".to_string(), Span::new(62, 69, 9, 1));
+        let diagnostic = Diagnostic::from(&error);
+        assert_eq!(
+            diagnostic.notes,
+            vec![
+                "in inner at line 3, column 12",
+                "in outer at line 6, column 5",
+                "in <script> at line 9, column 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_collapses_repeated_frames() {
+        let mut error = EvaluationError::runtime_error("Stack overflow".to_string()).with_span(Span::new(39, 45, 1, 40));
+        for _ in 0..128 {
+            error = error.in_call("f".to_string(), Span::new(39, 45, 1, 40));
+        }
+        error = error.in_call("g".to_string(), Span::new(60, 64, 2, 1));
+        assert_eq!(
+            Diagnostic::from(&error).notes,
+            vec![
+                "in f at line 1, column 40",
+                "... 127 more calls to f",
+                "in g at line 1, column 40",
+                "in <script> at line 2, column 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_caps_long_backtraces() {
+        let mut error = EvaluationError::runtime_error("Stack overflow".to_string());
+        for i in 0..50 {
+            error = error.in_call(format!("f{}", i), Span::new(i, i + 1, i + 1, 1));
+        }
+        let notes = Diagnostic::from(&error).notes;
+        assert_eq!(notes.len(), MAX_TRACE_NOTES + 1);
+        assert_eq!(notes[0], "in f0");
+        assert_eq!(notes[MAX_TRACE_NOTES / 2], "... 31 more frames");
+        assert_eq!(notes[MAX_TRACE_NOTES], "in <script> at line 50, column 1");
+    }
+
+    #[test]
+    fn it_renders_diagnostics_without_a_location() {
+        let diagnostic = Diagnostic::new(RUNTIME_ERROR, "Stack overflow.".to_string(), None);
+        assert_eq!(diagnostic.render("test.lox", ""), "error[E0300]: Stack overflow.\n");
+    }
+}
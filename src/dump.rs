@@ -0,0 +1,456 @@
+use crate::{
+    expressions::{expressions::Expression, logical_expression::LogicalExpressionOperator},
+    parser::Literal,
+    statement::{FunctionStatement, Statement},
+    tokens::{Span, Token, TokenType},
+};
+
+/// How tokens and syntax trees are written out for other tools.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One JSON value, with the span of every token and node.
+    Json,
+    /// Parenthesized prefix notation, like the book's `AstPrinter`.
+    Sexpr,
+}
+
+/// Just enough of JSON to describe tokens and trees.
+enum Json {
+    Null,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn string(s: impl ToString) -> Json {
+        Json::String(s.to_string())
+    }
+
+    // objects and arrays are spread over one line per entry
+    fn render(&self, depth: usize, out: &mut String) {
+        let indent = |depth: usize| "  ".repeat(depth);
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Number(n) if n.is_finite() => out.push_str(&n.to_string()),
+            // JSON has no infinity, e.g. for a literal too long for an f64;
+            // the lexeme or span still says what was written
+            Json::Number(_) => out.push_str("null"),
+            Json::String(s) => quote(s, out),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(&indent(depth + 1));
+                    item.render(depth + 1, out);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&indent(depth));
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    out.push_str(&indent(depth + 1));
+                    quote(key, out);
+                    out.push_str(": ");
+                    value.render(depth + 1, out);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&indent(depth));
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn quote(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn render(json: Json) -> String {
+    let mut out = String::new();
+    json.render(0, &mut out);
+    out.push('\n');
+    out
+}
+
+/// Writes out what the scanner produced.
+pub fn tokens(tokens: &[Token], format: Format) -> String {
+    match format {
+        Format::Json => render(Json::Array(tokens.iter().map(token_json).collect())),
+        Format::Sexpr => tokens.iter().map(|token| format!("{}\n", token_sexpr(token))).collect(),
+    }
+}
+
+/// Writes out a syntax tree.
+pub fn ast(statements: &[Box<Statement>], format: Format) -> String {
+    match format {
+        Format::Json => render(Json::Array(statements.iter().map(|statement| statement_json(statement)).collect())),
+        Format::Sexpr => statements.iter().map(|statement| format!("{}\n", statement_sexpr(statement))).collect(),
+    }
+}
+
+fn span_json(span: Span) -> Json {
+    Json::Object(vec![
+        ("start", Json::Number(span.start as f64)),
+        ("end", Json::Number(span.end as f64)),
+        ("line", Json::Number(span.line as f64)),
+        ("column", Json::Number(span.column as f64)),
+    ])
+}
+
+fn token_json(token: &Token) -> Json {
+    let literal = match &token.token_type {
+        TokenType::Number(n) => Json::Number(*n),
+        TokenType::String(s) => Json::string(s),
+        _ => Json::Null,
+    };
+    Json::Object(vec![
+        ("type", Json::string(token.token_type.name())),
+        ("lexeme", Json::string(&token.lexeme)),
+        ("literal", literal),
+        ("span", span_json(token.span)),
+    ])
+}
+
+fn token_sexpr(token: &Token) -> String {
+    let mut out = format!("({} ", token.token_type.name());
+    quote(&token.lexeme, &mut out);
+    match &token.token_type {
+        TokenType::Number(n) => out.push_str(&format!(" {}", n)),
+        TokenType::String(s) => {
+            out.push(' ');
+            quote(s, &mut out);
+        }
+        _ => {}
+    }
+    out.push_str(&format!(" {}:{})", token.span.line, token.span.column));
+    out
+}
+
+// a node: its type, its own fields, then its span
+fn node(kind: &str, mut fields: Vec<(&'static str, Json)>, span: Span) -> Json {
+    fields.insert(0, ("type", Json::string(kind)));
+    fields.push(("span", span_json(span)));
+    Json::Object(fields)
+}
+
+fn optional<T>(value: &Option<T>, to_json: impl Fn(&T) -> Json) -> Json {
+    value.as_ref().map(to_json).unwrap_or(Json::Null)
+}
+
+fn names<T: ToString>(names: &[T]) -> Json {
+    Json::Array(names.iter().map(|name| Json::string(name.to_string())).collect())
+}
+
+fn function_json(function: &FunctionStatement) -> Json {
+    node(
+        "Function",
+        vec![
+            ("name", Json::string(function.name)),
+            ("params", names(&function.params)),
+            ("body", statement_json(&function.body)),
+        ],
+        function.span,
+    )
+}
+
+fn statement_json(statement: &Statement) -> Json {
+    let span = statement.span();
+    match statement {
+        Statement::Expression(statement) => node("Expression", vec![("expression", expression_json(&statement.expression))], span),
+        Statement::Print(statement) => node("Print", vec![("expression", expression_json(&statement.expression))], span),
+        Statement::Var(statement) => node(
+            "Var",
+            vec![
+                ("name", Json::string(statement.name)),
+                ("initializer", optional(&statement.initializer, |e| expression_json(e))),
+            ],
+            span,
+        ),
+        Statement::Block(block) => node(
+            "Block",
+            vec![("statements", Json::Array(block.statements.iter().map(|s| statement_json(s)).collect()))],
+            span,
+        ),
+        Statement::If(statement) => node(
+            "If",
+            vec![
+                ("condition", expression_json(&statement.condition)),
+                ("then", statement_json(&statement.then_branch)),
+                ("else", optional(&statement.else_branch, |s| statement_json(s))),
+            ],
+            span,
+        ),
+        Statement::While(statement) => node(
+            "While",
+            vec![("condition", expression_json(&statement.condition)), ("body", statement_json(&statement.body))],
+            span,
+        ),
+        Statement::Function(function) => function_json(function),
+        Statement::Break(_) => node("Break", vec![], span),
+        Statement::Return(statement) => node("Return", vec![("value", optional(&statement.value, |e| expression_json(e)))], span),
+        Statement::Class(class) => node(
+            "Class",
+            vec![
+                ("name", Json::string(class.name)),
+                ("superclass", optional(&class.superclass, |e| expression_json(e))),
+                ("methods", Json::Array(class.methods.iter().map(function_json).collect())),
+            ],
+            span,
+        ),
+    }
+}
+
+fn literal_json(literal: &Literal) -> Json {
+    match literal {
+        Literal::Number(n) => Json::Number(*n),
+        Literal::String(s) => Json::string(s),
+        Literal::Boolean(b) => Json::string(b),
+        Literal::Nil => Json::Null,
+    }
+}
+
+fn logical_operator(operator: &LogicalExpressionOperator) -> &'static str {
+    match operator {
+        LogicalExpressionOperator::And => "and",
+        LogicalExpressionOperator::Or => "or",
+    }
+}
+
+fn expression_json(expression: &Expression) -> Json {
+    let span = expression.span();
+    match expression {
+        Expression::Binary(binary) => node(
+            "Binary",
+            vec![
+                ("operator", Json::string(&binary.op.lexeme)),
+                ("left", expression_json(&binary.left)),
+                ("right", expression_json(&binary.right)),
+            ],
+            span,
+        ),
+        Expression::Grouping(grouping) => node("Grouping", vec![("expression", expression_json(&grouping.child))], span),
+        Expression::Unary(unary) => node(
+            "Unary",
+            vec![("operator", Json::string(&unary.op.lexeme)), ("operand", expression_json(&unary.child))],
+            span,
+        ),
+        Expression::Call(call) => node(
+            "Call",
+            vec![
+                ("callee", expression_json(&call.callee)),
+                ("arguments", Json::Array(call.arguments.iter().map(|a| expression_json(a)).collect())),
+            ],
+            span,
+        ),
+        Expression::Literal(literal) => {
+            // booleans are told apart from strings by their kind
+            let kind = match literal.value {
+                Literal::Number(_) => "number",
+                Literal::String(_) => "string",
+                Literal::Boolean(_) => "boolean",
+                Literal::Nil => "nil",
+            };
+            node("Literal", vec![("kind", Json::string(kind)), ("value", literal_json(&literal.value))], span)
+        }
+        Expression::Logical(logical) => node(
+            "Logical",
+            vec![
+                ("operator", Json::string(logical_operator(&logical.operator))),
+                ("left", expression_json(&logical.left)),
+                ("right", expression_json(&logical.right)),
+            ],
+            span,
+        ),
+        Expression::Var(var) => node("Variable", vec![("name", Json::string(var.name))], span),
+        Expression::Assignment(assignment) => node(
+            "Assign",
+            vec![("name", Json::string(assignment.name)), ("value", expression_json(&assignment.child))],
+            span,
+        ),
+        Expression::Get(get) => node("Get", vec![("object", expression_json(&get.object)), ("name", Json::string(get.name))], span),
+        Expression::Set(set) => node(
+            "Set",
+            vec![
+                ("object", expression_json(&set.object)),
+                ("name", Json::string(set.name)),
+                ("value", expression_json(&set.value)),
+            ],
+            span,
+        ),
+        Expression::This(_) => node("This", vec![], span),
+        Expression::Super(superclass) => node("Super", vec![("method", Json::string(superclass.method))], span),
+    }
+}
+
+// `(head part part ...)`
+fn list(head: &str, parts: impl IntoIterator<Item = String>) -> String {
+    let mut out = format!("({}", head);
+    for part in parts {
+        out.push(' ');
+        out.push_str(&part);
+    }
+    out.push(')');
+    out
+}
+
+fn function_sexpr(head: &str, function: &FunctionStatement) -> String {
+    let params = list("", function.params.iter().map(|param| param.to_string())).replacen("( ", "(", 1);
+    list(head, [function.name.to_string(), params, statement_sexpr(&function.body)])
+}
+
+fn statement_sexpr(statement: &Statement) -> String {
+    match statement {
+        Statement::Expression(statement) => list(";", [expression_sexpr(&statement.expression)]),
+        Statement::Print(statement) => list("print", [expression_sexpr(&statement.expression)]),
+        Statement::Var(statement) => {
+            let mut parts = vec![statement.name.to_string()];
+            parts.extend(statement.initializer.iter().map(|e| expression_sexpr(e)));
+            list("var", parts)
+        }
+        Statement::Block(block) => list("block", block.statements.iter().map(|s| statement_sexpr(s))),
+        Statement::If(statement) => {
+            let mut parts = vec![expression_sexpr(&statement.condition), statement_sexpr(&statement.then_branch)];
+            parts.extend(statement.else_branch.iter().map(|s| statement_sexpr(s)));
+            list("if", parts)
+        }
+        Statement::While(statement) => list("while", [expression_sexpr(&statement.condition), statement_sexpr(&statement.body)]),
+        Statement::Function(function) => function_sexpr("fun", function),
+        Statement::Break(_) => "(break)".to_string(),
+        Statement::Return(statement) => list("return", statement.value.iter().map(|e| expression_sexpr(e))),
+        Statement::Class(class) => {
+            let mut parts = vec![class.name.to_string()];
+            parts.extend(class.superclass.iter().map(|e| list("<", [expression_sexpr(e)])));
+            parts.extend(class.methods.iter().map(|method| function_sexpr("method", method)));
+            list("class", parts)
+        }
+    }
+}
+
+fn expression_sexpr(expression: &Expression) -> String {
+    match expression {
+        Expression::Binary(binary) => list(&binary.op.lexeme, [expression_sexpr(&binary.left), expression_sexpr(&binary.right)]),
+        Expression::Grouping(grouping) => list("group", [expression_sexpr(&grouping.child)]),
+        Expression::Unary(unary) => list(&unary.op.lexeme, [expression_sexpr(&unary.child)]),
+        Expression::Call(call) => {
+            let mut parts = vec![expression_sexpr(&call.callee)];
+            parts.extend(call.arguments.iter().map(|a| expression_sexpr(a)));
+            list("call", parts)
+        }
+        Expression::Literal(literal) => match &literal.value {
+            Literal::String(s) => {
+                let mut out = String::new();
+                quote(s, &mut out);
+                out
+            }
+            value => value.to_string(),
+        },
+        Expression::Logical(logical) => list(
+            logical_operator(&logical.operator),
+            [expression_sexpr(&logical.left), expression_sexpr(&logical.right)],
+        ),
+        Expression::Var(var) => var.name.to_string(),
+        Expression::Assignment(assignment) => list("=", [assignment.name.to_string(), expression_sexpr(&assignment.child)]),
+        Expression::Get(get) => list(".", [expression_sexpr(&get.object), get.name.to_string()]),
+        Expression::Set(set) => list(
+            "=",
+            [list(".", [expression_sexpr(&set.object), set.name.to_string()]), expression_sexpr(&set.value)],
+        ),
+        Expression::This(_) => "this".to_string(),
+        Expression::Super(superclass) => list("super", [superclass.method.to_string()]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Scanner;
+
+    use super::*;
+
+    fn sexpr(source: &str) -> String {
+        ast(&crate::parse_syntax(source).unwrap(), Format::Sexpr)
+    }
+
+    #[test]
+    fn it_prints_trees_like_the_books_ast_printer() {
+        assert_eq!(sexpr("-123 * (45.67);"), "(; (* (- 123) (group 45.67)))\n");
+        assert_eq!(sexpr("var a = \"hi\"; a = nil or !true;"), "(var a \"hi\")\n(; (= a (or nil (! true))))\n");
+        assert_eq!(
+            sexpr("class B < A { init(n) { this.n = super.f(n); } }"),
+            "(class B (< A) (method init (n) (block (; (= (. this n) (call (super f) n))))))\n"
+        );
+        assert_eq!(sexpr("fun f() { return; } if (x) print 1; else {}"), "(fun f () (block (return)))\n(if x (print 1) (block))\n");
+    }
+
+    #[test]
+    fn it_prints_tokens_with_their_values_and_positions() {
+        let mut scanner = Scanner::new("print \"a\\b\" 1.5;".to_string());
+        assert_eq!(
+            tokens(scanner.scan_tokens(), Format::Sexpr),
+            "(PRINT \"print\" 1:1)\n(STRING \"\\\"a\\\\b\\\"\" \"a\\\\b\" 1:7)\n(NUMBER \"1.5\" 1.5 1:13)\n(SEMICOLON \";\" 1:16)\n(EOF \"\" 1:17)\n"
+        );
+    }
+
+    #[test]
+    fn it_writes_json_with_spans() {
+        let json = ast(&crate::parse_syntax("print 1;").unwrap(), Format::Json);
+        assert_eq!(
+            json,
+            r#"[
+  {
+    "type": "Print",
+    "expression": {
+      "type": "Literal",
+      "kind": "number",
+      "value": 1,
+      "span": {
+        "start": 6,
+        "end": 7,
+        "line": 1,
+        "column": 7
+      }
+    },
+    "span": {
+      "start": 0,
+      "end": 8,
+      "line": 1,
+      "column": 1
+    }
+  }
+]
+"#
+        );
+
+        let mut scanner = Scanner::new("\"q\"".to_string());
+        let json = tokens(scanner.scan_tokens(), Format::Json);
+        assert!(json.contains(r#""lexeme": "\"q\"","#), "{}", json);
+        assert!(json.contains(r#""literal": "q","#), "{}", json);
+    }
+
+    #[test]
+    fn it_writes_numbers_json_cant_hold_as_null() {
+        let huge = "9".repeat(400);
+        let mut scanner = Scanner::new(huge.clone());
+        let json = tokens(scanner.scan_tokens(), Format::Json);
+        assert!(json.contains(&format!(r#""lexeme": "{}","#, huge)), "{}", json);
+        assert!(json.contains(r#""literal": null,"#), "{}", json);
+
+        let json = ast(&crate::parse_syntax(&format!("print {};", huge)).unwrap(), Format::Json);
+        assert!(json.contains(r#""value": null,"#), "{}", json);
+    }
+}
@@ -1,82 +1,192 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::{RefCell, RefMut}, collections::HashMap, rc::Rc};
 
-use crate::{interpreter::EvaluationError, expressions::expressions::ExpressionResult};
+use crate::{collector::{address, Collector, Object, Trace}, interpreter::EvaluationError, expressions::expressions::ExpressionResult, interner::Symbol, limits::{Limits, Meter}, output::Sink, resolver::Slot, vm::heap::GcConfig};
 
-pub struct EnvironmentNode {
-    pub values: HashMap<String, ExpressionResult>,
-    pub parent: Option<Rc<RefCell<EnvironmentNode>>>,
+/// The locals of one block or call, in the order they were declared. The
+/// resolver numbers declarations the same way, so a local is found by index
+/// without looking at its name.
+pub struct Scope {
+    pub values: Vec<ExpressionResult>,
+    pub parent: Option<Rc<RefCell<Scope>>>,
 }
 
-
-impl EnvironmentNode {
-    pub fn define(&mut self, name: String, value: ExpressionResult) {
-        self.values.insert(name, value);
-    }
-
-    pub fn set(&mut self, name: &String, value: ExpressionResult) -> Result<(), EvaluationError> {
-        match (self.values.get_mut(name), self.parent.clone()) {
-            (None, None) => Err(EvaluationError::runtime_error(format!("Undefined variable '{}'", name))),
-            (None, Some(parent)) => parent.borrow_mut().set(name, value),
-            (Some(val), _) => {
-                *val = value;
-                Ok(())
-            }
+impl Trace for Scope {
+    fn trace(&self, refs: &mut Vec<usize>) {
+        if let Some(parent) = &self.parent {
+            refs.push(address(parent));
         }
-    }
-
-    pub fn get(&self, name: &String) -> Result<ExpressionResult, EvaluationError> {
-        match (self.values.get(name), self.parent.clone()) {
-            (None, None) => Err(EvaluationError::runtime_error(format!("Undefined variable '{}'", name))),
-            (Some(value), _) => Ok(value.clone()),
-            (_, Some(parent)) => {
-                let p = parent.borrow();
-                let v = p.get(name)?;
-                return Ok(v.to_owned());
-            }
+        for value in &self.values {
+            value.trace(refs);
         }
     }
 }
 
+pub type Globals = HashMap<Symbol, ExpressionResult>;
+
 pub struct Environment {
-    e: Rc<RefCell<EnvironmentNode>>,
+    // None at the top level, where every declaration is a global
+    scope: Option<Rc<RefCell<Scope>>>,
+    globals: Rc<RefCell<Globals>>,
+    // where `print` writes, shared by every scope
+    output: Sink,
+    // the run's usage against its limits, shared by every scope
+    meter: Rc<RefCell<Meter>>,
+    // frees the scopes that closures keep alive in cycles, shared by every scope
+    collector: Rc<RefCell<Collector>>,
+}
+
+impl Trace for Environment {
+    fn trace(&self, refs: &mut Vec<usize>) {
+        if let Some(scope) = &self.scope {
+            refs.push(address(scope));
+        }
+        refs.push(address(&self.globals));
+    }
 }
 
 impl Clone for Environment {
     fn clone(&self) -> Self {
         Environment {
-            e: Rc::clone(&self.e)
+            scope: self.scope.clone(),
+            globals: Rc::clone(&self.globals),
+            output: self.output.clone(),
+            meter: Rc::clone(&self.meter),
+            collector: Rc::clone(&self.collector),
         }
     }
 }
 
+impl std::fmt::Debug for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // environments can be cyclic through closures, so don't print their contents
+        write!(f, "Environment")
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Environment {
     pub fn new() -> Environment {
-        Environment {
-            e: Rc::new(RefCell::new(EnvironmentNode {
-                parent: None,
-                values: HashMap::new(),
-            })),
+        Environment::with_gc(GcConfig::default())
+    }
+
+    pub fn with_gc(config: GcConfig) -> Environment {
+        let environment = Environment {
+            scope: None,
+            globals: Rc::new(RefCell::new(HashMap::new())),
+            output: Sink::stdout(),
+            meter: Rc::new(RefCell::new(Meter::new(Limits::default()))),
+            collector: Rc::new(RefCell::new(Collector::new(config))),
+        };
+        Collector::track(&environment.collector, Object::Globals(environment.globals.clone()));
+        environment
+    }
+
+    /// Declares a global at the top level, or the next slot of the current
+    /// scope anywhere else.
+    pub fn define (&mut self, name: Symbol, value: ExpressionResult) {
+        match &self.scope {
+            Some(scope) => scope.borrow_mut().values.push(value),
+            None => {
+                self.globals.borrow_mut().insert(name, value);
+            }
         }
     }
 
-    pub fn define (&mut self, name: String, value: ExpressionResult) {
-        self.e.borrow_mut().define(name, value);
+    /// A local at the slot computed by the resolver.
+    pub fn get_at(&self, slot: Slot) -> ExpressionResult {
+        self.ancestor(slot.depth).borrow().values[slot.index].clone()
     }
 
-    pub fn set(&mut self, name: &String, value: ExpressionResult) -> Result<(), EvaluationError> {
-        self.e.borrow_mut().set(name, value)
+    pub fn set_at(&mut self, slot: Slot, value: ExpressionResult) {
+        self.ancestor(slot.depth).borrow_mut().values[slot.index] = value;
     }
 
-    pub fn get(&self, name: &String) -> Result<ExpressionResult, EvaluationError> {
-        self.e.borrow().get(name)
+    pub fn get_global(&self, name: Symbol) -> Result<ExpressionResult, EvaluationError> {
+        match self.globals.borrow().get(&name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(EvaluationError::runtime_error(format!("Undefined variable '{}'", name))),
+        }
+    }
+
+    pub fn set_global(&mut self, name: Symbol, value: ExpressionResult) -> Result<(), EvaluationError> {
+        match self.globals.borrow_mut().get_mut(&name) {
+            Some(global) => {
+                *global = value;
+                Ok(())
+            }
+            None => Err(EvaluationError::runtime_error(format!("Undefined variable '{}'", name))),
+        }
+    }
+
+    fn ancestor(&self, distance: usize) -> Rc<RefCell<Scope>> {
+        let mut scope = self.scope.clone().expect("resolved a local outside of any scope");
+        for _ in 0..distance {
+            let parent = scope.borrow().parent.clone().expect("resolved scope depth is deeper than the environment");
+            scope = parent;
+        }
+        scope
     }
 
     pub fn enclosed(&mut self) -> Self {
-        let n = Self::new();
-        n.e.borrow_mut().parent = Some(self.e.clone());
-        return n;
+        let scope = Rc::new(RefCell::new(Scope {
+            values: vec![],
+            parent: self.scope.clone(),
+        }));
+        Collector::track(&self.collector, Object::Scope(scope.clone()));
+        Environment {
+            scope: Some(scope),
+            globals: Rc::clone(&self.globals),
+            output: self.output.clone(),
+            meter: Rc::clone(&self.meter),
+            collector: Rc::clone(&self.collector),
+        }
+    }
+
+    /// Whether both environments look names up in the same place.
+    pub fn same_scope(&self, other: &Environment) -> bool {
+        let same_locals = match (&self.scope, &other.scope) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        same_locals && Rc::ptr_eq(&self.globals, &other.globals)
+    }
+
+    pub fn collector(&self) -> Rc<RefCell<Collector>> {
+        Rc::clone(&self.collector)
+    }
+
+    /// Lets go of the globals without emptying them. Functions that close
+    /// over them keep them alive; if nothing outside those cycles does, the
+    /// collector can then free them all.
+    pub fn release_globals(&mut self) {
+        self.globals = Rc::new(RefCell::new(HashMap::new()));
     }
-    
+
+    pub fn meter(&self) -> RefMut<'_, Meter> {
+        self.meter.borrow_mut()
+    }
+
+    pub fn output(&self) -> Sink {
+        self.output.clone()
+    }
+
+    pub fn set_output(&mut self, output: Sink) {
+        self.output = output;
+    }
+
+    /// Every global and its value, sorted by name.
+    pub fn globals(&self) -> Vec<(Symbol, ExpressionResult)> {
+        let mut globals: Vec<_> = self.globals.borrow().iter().map(|(name, value)| (*name, value.clone())).collect();
+        globals.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        globals
+    }
+
 }
 
 // tests
@@ -86,39 +196,48 @@ mod tests {
 
     use super::*;
 
+    fn number(n: f64) -> ExpressionResult {
+        ExpressionResult::Literal(Literal::Number(n))
+    }
+
     #[test]
     fn test_environment() {
         let mut env = Environment::new();
-        env.define("a".to_string(), ExpressionResult::Literal(Literal::Number(1.0)));
-        env.define("b".to_string(), ExpressionResult::Literal(Literal::Number(2.0)));
-        env.define("c".to_string(), ExpressionResult::Literal(Literal::Number(3.0)));
+        env.define(Symbol::intern("a"), number(1.0));
+        env.define(Symbol::intern("b"), number(2.0));
+        env.define(Symbol::intern("c"), number(3.0));
 
-        assert_eq!(env.get(&"a".to_string()).unwrap(), ExpressionResult::Literal(Literal::Number(1.0)));
-        assert_eq!(env.get(&"b".to_string()).unwrap(), ExpressionResult::Literal(Literal::Number(2.0)));
-        assert_eq!(env.get(&"c".to_string()).unwrap(), ExpressionResult::Literal(Literal::Number(3.0)));
+        assert_eq!(env.get_global(Symbol::intern("a")).unwrap(), number(1.0));
+        assert_eq!(env.get_global(Symbol::intern("b")).unwrap(), number(2.0));
+        assert_eq!(env.get_global(Symbol::intern("c")).unwrap(), number(3.0));
     }
 
     #[test]
     fn nested_environments() {
         let mut env = Environment::new();
-        env.define("a".to_string(), ExpressionResult::Literal(Literal::Number(1.0)));
-        env.define("b".to_string(), ExpressionResult::Literal(Literal::Number(2.0)));
+        env.define(Symbol::intern("a"), number(1.0));
 
         let mut env2 = env.enclosed();
-        env2.define("b".to_string(), ExpressionResult::Literal(Literal::Number(6.0)));
-
-        assert_eq!(env2.get(&"b".to_string()).unwrap(), ExpressionResult::Literal(Literal::Number(6.0)));
-        assert_eq!(env2.get(&"a".to_string()).unwrap(), ExpressionResult::Literal(Literal::Number(1.0)));
+        env2.define(Symbol::intern("b"), number(6.0));
+        env2.define(Symbol::intern("c"), number(7.0));
+        let mut env3 = env2.enclosed();
+        env3.define(Symbol::intern("d"), number(8.0));
+
+        assert_eq!(env3.get_at(Slot { depth: 0, index: 0 }), number(8.0));
+        assert_eq!(env3.get_at(Slot { depth: 1, index: 1 }), number(7.0));
+        // locals never shadow globals by name
+        assert_eq!(env3.get_global(Symbol::intern("a")).unwrap(), number(1.0));
+        assert!(env3.get_global(Symbol::intern("b")).is_err());
     }
 
     #[test]
     fn nested_reassignment() {
         let mut env = Environment::new();
-        env.define("a".to_string(), ExpressionResult::Literal(Literal::Number(1.0)));
-
         let mut env2 = env.enclosed();
+        env2.define(Symbol::intern("a"), number(1.0));
 
-        env2.set(&"a".to_string(), ExpressionResult::Literal(Literal::Number(2.0)));
-        assert_eq!(env2.get(&"a".to_string()).unwrap(), ExpressionResult::Literal(Literal::Number(2.0)));
+        let mut env3 = env2.enclosed();
+        env3.set_at(Slot { depth: 1, index: 0 }, number(2.0));
+        assert_eq!(env2.get_at(Slot { depth: 0, index: 0 }), number(2.0));
     }
 }
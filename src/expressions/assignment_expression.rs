@@ -1,27 +1,43 @@
 use std::{rc::Rc, cell::RefCell};
 
-use crate::{environment::Environment, parser::Literal, interpreter::EvaluationError};
+use crate::{environment::Environment, parser::Literal, interpreter::EvaluationError, resolver::{Resolver, Slot}, tokens::Span, interner::Symbol, vm::compiler::Compiler};
 
 use super::expressions::{Expression, ExpressionResult};
 
 
 #[derive(Debug, Clone)]
 pub struct AssignmentExpression {
-    pub name: String,
-    child: Box<Expression>,
+    pub name: Symbol,
+    pub child: Box<Expression>,
+    // where the declaration lives, None for globals
+    slot: Option<Slot>,
+    pub span: Span,
 }
 
 impl AssignmentExpression {
-    pub fn new(name: String, child: Box<Expression>) -> Self {
-        Self { name, child }
+    pub fn new(name: Symbol, child: Box<Expression>, span: Span) -> Self {
+        Self { name, child, slot: None, span }
     }
 
     pub fn evaluate(&self, environment: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
         let v = self.child.evaluate(environment)?;
-        environment.set(&self.name, v.clone())?;
+        match self.slot {
+            Some(slot) => environment.set_at(slot, v.clone()),
+            None => environment.set_global(self.name, v.clone())?,
+        }
         Ok(v)
     }
 
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.child.compile(compiler);
+        compiler.set_variable(self.name.as_str());
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.child.resolve(resolver);
+        self.slot = resolver.resolve_local(self.name);
+    }
+
     pub fn children(&self) -> Vec<&Expression> {
         vec![&self.child]
     }
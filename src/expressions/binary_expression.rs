@@ -1,18 +1,19 @@
-use crate::{tokens::{Token, TokenType}, parser::Literal, interpreter::EvaluationError, environment::Environment};
+use crate::{tokens::{Span, Token, TokenType}, parser::Literal, interpreter::EvaluationError, environment::Environment, resolver::Resolver, vm::{chunk::OpCode, compiler::Compiler}};
 use super::expressions::{Expression, ExpressionResult};
 use std::{rc::Rc, cell::RefCell};
 
 
 #[derive(Debug, Clone)]
 pub struct BinaryExpression {
-    op: Token,
-    left: Box<Expression>,
-    right: Box<Expression>,
+    pub op: Token,
+    pub left: Box<Expression>,
+    pub right: Box<Expression>,
+    pub span: Span,
 }
 
 impl BinaryExpression {
-    pub fn new(op: Token, left: Box<Expression>, right: Box<Expression>) -> Self {
-        Self { op, left, right }
+    pub fn new(op: Token, left: Box<Expression>, right: Box<Expression>, span: Span) -> Self {
+        Self { op, left, right, span }
     }
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
         let left = self.left.evaluate(env)?;
@@ -21,58 +22,75 @@ impl BinaryExpression {
         match &self.op.token_type {
                         TokenType::Plus => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Number(n + m))),
-                (ExpressionResult::Literal(Literal::String(n)), ExpressionResult::Literal(Literal::String(m))) => Ok(ExpressionResult::Literal(Literal::String(n.to_owned() + m.as_str()))),
-                (ExpressionResult::Literal(Literal::String(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::String(n.to_owned() + m.to_string().as_str()))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers or two strings, got: {:?} {:?}", left, right)))
+                (ExpressionResult::Literal(Literal::String(n)), ExpressionResult::Literal(Literal::String(m))) => Ok(ExpressionResult::Literal(Literal::String(format!("{}{}", n, m).into()))),
+                (ExpressionResult::Literal(Literal::String(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::String(format!("{}{}", n, m).into()))),
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers or two strings, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::Minus => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Number(n - m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::Star => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Number(n * m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::Slash => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Number(n / m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::Greater => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n > m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::GreaterEqual => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n >= m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::Less => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n < m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
             TokenType::LessEqual => match (&left, &right) {
                 (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n <= m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {:?} {:?}", left, right)))
+                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, got: {} {}", left.describe(), right.describe())))
             },
-            TokenType::BangEqual => match (&left, &right) {
-                (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n != m))),
-                (ExpressionResult::Literal(Literal::String(n)), ExpressionResult::Literal(Literal::String(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n != m))),
-                (ExpressionResult::Literal(Literal::Boolean(n)), ExpressionResult::Literal(Literal::Boolean(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n != m))),
-                _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, two strings, or two booleans, got: {:?} {:?}", left, right)))
-            },
-            TokenType::EqualEqual => {
-                return match (&left, &right) {
-                    (ExpressionResult::Literal(Literal::Number(n)), ExpressionResult::Literal(Literal::Number(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n == m))),
-                    (ExpressionResult::Literal(Literal::String(n)), ExpressionResult::Literal(Literal::String(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n == m))),
-                    (ExpressionResult::Literal(Literal::Boolean(n)), ExpressionResult::Literal(Literal::Boolean(m))) => Ok(ExpressionResult::Literal(Literal::Boolean(n == m))),
-                    _ => Err(EvaluationError::runtime_error(format!("Expected two numbers, two strings, or two booleans, got: {:?} {:?}", left, right)))
-                };
+            // values of different types are never equal, and nothing is an error
+            TokenType::BangEqual => Ok(ExpressionResult::Literal(Literal::Boolean(left != right))),
+            TokenType::EqualEqual => Ok(ExpressionResult::Literal(Literal::Boolean(left == right))),
+            _ => {
+                panic!("Unexpected token: {:?}", self.op);
+            }
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.left.compile(compiler);
+        self.right.compile(compiler);
+        match &self.op.token_type {
+            TokenType::Plus => compiler.emit_op(OpCode::Add),
+            TokenType::Minus => compiler.emit_op(OpCode::Subtract),
+            TokenType::Star => compiler.emit_op(OpCode::Multiply),
+            TokenType::Slash => compiler.emit_op(OpCode::Divide),
+            TokenType::Greater => compiler.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => compiler.emit_op(OpCode::GreaterEqual),
+            TokenType::Less => compiler.emit_op(OpCode::Less),
+            TokenType::LessEqual => compiler.emit_op(OpCode::LessEqual),
+            TokenType::BangEqual => {
+                compiler.emit_op(OpCode::Equal);
+                compiler.emit_op(OpCode::Not);
             }
+            TokenType::EqualEqual => compiler.emit_op(OpCode::Equal),
             _ => {
                 panic!("Unexpected token: {:?}", self.op);
             }
         }
     }
 
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.left.resolve(resolver);
+        self.right.resolve(resolver);
+    }
+
     pub fn children(&self) -> Vec<&Expression> {
         vec![&self.left, &self.right]
     }
@@ -1,36 +1,57 @@
-use crate::{tokens::Token, environment::Environment, parser::Literal, interpreter::EvaluationError};
+use crate::{tokens::{Span, Token}, environment::Environment, parser::Literal, interpreter::EvaluationError, resolver::Resolver, vm::{chunk::OpCode, compiler::Compiler}};
 
 use super::expressions::{Expression, ExpressionResult};
 
 
 #[derive(Debug, Clone)]
 pub struct CallExpression {
-    callee: Box<Expression>,
-    paren: Token,
-    arguments: Vec<Box<Expression>>,
+    pub callee: Box<Expression>,
+    pub paren: Token,
+    pub arguments: Vec<Box<Expression>>,
+    pub span: Span,
 }
 
 impl CallExpression {
-    pub fn new(callee: Box<Expression>, paren: Token, arguments: Vec<Box<Expression>>) -> Self {
-        Self { callee, paren, arguments }
+    pub fn new(callee: Box<Expression>, paren: Token, arguments: Vec<Box<Expression>>, span: Span) -> Self {
+        Self { callee, paren, arguments, span }
     }
 
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
         let callee = self.callee.evaluate(env)?;
+        // arguments are evaluated before anything is checked, like the VM does
         let mut args = Vec::new();
-        let mut new_env = env.enclosed();
+        for arg in &self.arguments {
+            args.push(arg.evaluate(env)?);
+        }
         match callee {
             ExpressionResult::Callable(callable) => {
-                if self.arguments.len() != callable.arity() {
+                if args.len() != callable.arity() {
                     return Err(EvaluationError::runtime_error(format!("Expected {} arguments but got {}", callable.arity(), args.len())))
                 }
-                for arg in &self.arguments {
-                    args.push(arg.evaluate(env)?);
+                // a call nested too deeply fails here, at the caller, so the
+                // callee doesn't appear in the backtrace
+                if callable.runs_lox() {
+                    env.meter().check_next_call()?;
                 }
-
-                callable.call(&mut new_env, args)
+                callable.call(args).map_err(|e| e.in_call(callable.name(), self.span))
             }
-            _ => Err(EvaluationError::runtime_error(format!("Can only call functions, not {:?}", callee)))
+            _ => Err(EvaluationError::runtime_error(format!("Can only call functions, not {}", callee.describe())))
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.callee.compile(compiler);
+        for arg in &self.arguments {
+            arg.compile(compiler);
+        }
+        // the parser caps calls at 255 arguments, so the count fits a byte
+        compiler.emit_op_byte(OpCode::Call, self.arguments.len() as u8);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.callee.resolve(resolver);
+        for arg in &mut self.arguments {
+            arg.resolve(resolver);
         }
     }
 
@@ -1,11 +1,16 @@
 use std::{any::Any, cell::RefCell, rc::Rc, fmt::{Display, Formatter}};
 
-use crate::{parser::Literal, interpreter::EvaluationError, environment::{self, Environment}, statement::{Executable, Statement}, tokens::Token};
+use crate::{class::{Class, Instance}, collector::{address, Trace}, parser::Literal, interpreter::EvaluationError, environment::{self, Environment}, resolver::{Resolver, Slot}, statement::{Executable, Statement, StatementResult}, tokens::{Span, Token}, interner::Symbol, vm::compiler::Compiler};
 
-use super::{binary_expression::BinaryExpression, grouping_expression::GroupingExpression, unary_expression::UnaryExpression, literal_expression::LiteralExpression, call_expression::CallExpression, logical_expression::LogicalExpression, var_expression::VarExpression, assignment_expression::AssignmentExpression};
+use super::{binary_expression::BinaryExpression, grouping_expression::GroupingExpression, unary_expression::UnaryExpression, literal_expression::LiteralExpression, call_expression::CallExpression, logical_expression::LogicalExpression, var_expression::VarExpression, assignment_expression::AssignmentExpression, get_expression::GetExpression, set_expression::SetExpression, this_expression::ThisExpression, super_expression::SuperExpression};
 
+// room a call's Rust frames need before the stack has to grow, and how
+// much to grow it by
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
 
-/**
+
+/*
 pub trait Callable: std::fmt::Debug + Any {
     fn arity(&self) -> usize;
     fn call(&self, env: &mut Environment, args: Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError>;
@@ -28,45 +33,143 @@ impl Callable for Clock {
 
 #[derive(Debug, Clone)]
 pub enum Callable {
-    Clock,
-    UserDefined(Box<Statement>, Vec<String>),
+    Native(NativeFunction),
+    UserDefined(Function),
+    Class(Rc<Class>),
+}
+
+pub type NativeFn = dyn Fn(Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError>;
+
+/// A function implemented in Rust by the host, callable from Lox like any
+/// other. Arguments are checked against `arity` before it is called.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: Symbol,
+    arity: usize,
+    function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(name: Symbol, arity: usize, function: Rc<NativeFn>) -> Self {
+        Self { name, arity, function }
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NativeFunction({}/{})", self.name, self.arity)
+    }
 }
 
+/// A function declared in Lox, along with the environment it was declared in.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: Symbol,
+    params: Vec<Symbol>,
+    body: Rc<Statement>,
+    closure: Environment,
+    is_initializer: bool,
+}
+
+impl Function {
+    pub fn new(name: Symbol, params: Vec<Symbol>, body: Rc<Statement>, closure: Environment, is_initializer: bool) -> Self {
+        Self { name, params, body, closure, is_initializer }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns a copy of this method whose closure has `this` bound to `instance`.
+    pub fn bind(&self, instance: Rc<RefCell<Instance>>) -> Function {
+        let mut env = self.closure.clone().enclosed();
+        env.define(Symbol::intern("this"), ExpressionResult::Instance(instance));
+        Function::new(self.name, self.params.clone(), Rc::clone(&self.body), env, self.is_initializer)
+    }
+
+    pub fn call(&self, args: Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError> {
+        // parameters live in a scope enclosed by the declaring environment,
+        // not the caller's, so name resolution is lexical
+        let env = &mut self.closure.clone().enclosed();
+        for (i, arg) in args.iter().enumerate() {
+            env.define(self.params[i], arg.clone());
+        }
+
+        env.meter().enter_call()?;
+        // each Lox call takes several Rust frames, so the stack is grown on
+        // the heap as needed and only the call depth limit bounds recursion
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || self.body.execute(env));
+        env.meter().exit_call();
+        let result = result?;
+        if self.is_initializer {
+            // initializers always hand back the instance, even on a bare `return;`
+            return Ok(self.closure.get_at(Slot { depth: 0, index: 0 }));
+        }
+        match result {
+            StatementResult::Return(value) => Ok(value),
+            StatementResult::None => Ok(ExpressionResult::Literal(Literal::Nil)),
+        }
+    }
+}
 
+impl Trace for Function {
+    fn trace(&self, refs: &mut Vec<usize>) {
+        self.closure.trace(refs);
+    }
+}
 
 impl Callable {
     pub fn arity(&self) -> usize {
         match self {
-            Callable::Clock => 0,
-            Callable::UserDefined(_stmt, params) => params.len(),
+            Callable::Native(native) => native.arity,
+            Callable::UserDefined(function) => function.arity(),
+            Callable::Class(class) => class.arity(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Callable::Native(native) => native.name.to_string(),
+            Callable::UserDefined(function) => function.name.to_string(),
+            Callable::Class(class) => class.name.to_string(),
+        }
+    }
+
+    /// Whether calling this runs Lox code, which counts against the call
+    /// depth limit. Natives don't, and nor do classes without `init`.
+    pub fn runs_lox(&self) -> bool {
+        match self {
+            Callable::Native(_) => false,
+            Callable::UserDefined(_) => true,
+            Callable::Class(class) => class.find_method(Symbol::intern("init")).is_some(),
         }
     }
 
-    pub fn call(&self, env: &mut Environment, args: Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError> {
+    pub fn call(&self, args: Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError> {
         
         match self {
-            Callable::Clock => Ok(ExpressionResult::Literal(Literal::Number(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as f64))),
-            Callable::UserDefined(stmt, arg_names) => { 
-                for (i, arg) in args.iter().enumerate() {
-                    env.define(arg_names[i].clone(), arg.clone());
-                }
-
-                let result = stmt.execute(env);
-                if let Err(e) = result {
-                    return Err(e);
-                }
-                Ok(ExpressionResult::None)
-            },
+            Callable::Native(native) => (native.function)(args),
+            Callable::UserDefined(function) => function.call(args),
+            Callable::Class(class) => Class::instantiate(class, args),
         }
     }
 
     fn partial_eq(&self, other: &Callable) -> bool {
         match self {
-            Callable::Clock => match other {
-                Callable::Clock => true,
+            Callable::Native(native) => match other {
+                Callable::Native(other) => Rc::ptr_eq(&native.function, &other.function),
+                _ => false,
+            },
+            // each evaluation of a declaration closes over a scope of its
+            // own, so the body and the scope together identify the function
+            Callable::UserDefined(function) => match other {
+                Callable::UserDefined(other) => Rc::ptr_eq(&function.body, &other.body) && function.closure.same_scope(&other.closure),
+                _ => false,
+            },
+            Callable::Class(class) => match other {
+                Callable::Class(other) => Rc::ptr_eq(class, other),
                 _ => false,
             },
-            Callable::UserDefined(stmt, arity) => false
         }
     }
 }
@@ -77,20 +180,74 @@ impl<'a> PartialEq for Callable {
     }
 }
 
+impl Display for Callable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Callable::Native(_) => write!(f, "<native fn>"),
+            Callable::UserDefined(function) => write!(f, "<fn {}>", function.name),
+            Callable::Class(class) => write!(f, "{}", class.name),
+        }
+    }
+}
+
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum ExpressionResult {
-    None,
     Literal(Literal),
     Callable(Callable),
+    Instance(Rc<RefCell<Instance>>),
 }
 
 impl<'a> ExpressionResult{
+    /// The name of the value's type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ExpressionResult::Literal(Literal::Nil) => "nil",
+            ExpressionResult::Literal(Literal::Boolean(_)) => "boolean",
+            ExpressionResult::Literal(Literal::Number(_)) => "number",
+            ExpressionResult::Literal(Literal::String(_)) => "string",
+            ExpressionResult::Callable(Callable::Class(_)) => "class",
+            ExpressionResult::Callable(_) => "function",
+            ExpressionResult::Instance(_) => "instance",
+        }
+    }
+
+    /// Like `Display`, but quotes strings so they stand out in error messages.
+    pub fn describe(&self) -> String {
+        match self {
+            ExpressionResult::Literal(Literal::String(s)) => format!("{:?}", s),
+            value => value.to_string(),
+        }
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
-            ExpressionResult::None => false,
             ExpressionResult::Literal(literal) => literal.is_truthy(),
             ExpressionResult::Callable(callable) => true,
+            ExpressionResult::Instance(_instance) => true,
+        }
+    }
+}
+
+impl Trace for ExpressionResult {
+    fn trace(&self, refs: &mut Vec<usize>) {
+        match self {
+            ExpressionResult::Literal(_) | ExpressionResult::Callable(Callable::Native(_)) => {}
+            ExpressionResult::Callable(Callable::UserDefined(function)) => function.trace(refs),
+            ExpressionResult::Callable(Callable::Class(class)) => refs.push(address(class)),
+            ExpressionResult::Instance(instance) => refs.push(address(instance)),
+        }
+    }
+}
+
+impl PartialEq for ExpressionResult {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ExpressionResult::Literal(a), ExpressionResult::Literal(b)) => a == b,
+            (ExpressionResult::Callable(a), ExpressionResult::Callable(b)) => a == b,
+            // instances are only ever equal to themselves
+            (ExpressionResult::Instance(a), ExpressionResult::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
         }
     }
 }
@@ -98,15 +255,15 @@ impl<'a> ExpressionResult{
 impl Display for ExpressionResult {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            ExpressionResult::None => write!(f, "None"),
             ExpressionResult::Literal(literal) => write!(f, "{}", literal),
-            ExpressionResult::Callable(callable) => write!(f, "Callable"),
+            ExpressionResult::Callable(callable) => write!(f, "{}", callable),
+            ExpressionResult::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
         }
     }
 }
 
 
-/**
+/*
 pub trait Expression: std::fmt::Debug + Any{
     fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError>;
     fn children(&self) -> Vec<&Expression>;
@@ -133,11 +290,15 @@ pub enum Expression {
     Logical(LogicalExpression),
     Var(VarExpression),
     Assignment(AssignmentExpression),
+    Get(GetExpression),
+    Set(SetExpression),
+    This(ThisExpression),
+    Super(SuperExpression),
 }
 
 impl Expression {
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
-        match self {
+        let result = match self {
             Expression::Binary(expr) => expr.evaluate(env),
             Expression::Grouping(expr) => expr.evaluate(env),
             Expression::Unary(expr) => expr.evaluate(env),
@@ -146,6 +307,29 @@ impl Expression {
             Expression::Logical(expr) => expr.evaluate(env),
             Expression::Var(expr) => expr.evaluate(env),
             Expression::Assignment(expr) => expr.evaluate(env),
+            Expression::Get(expr) => expr.evaluate(env),
+            Expression::Set(expr) => expr.evaluate(env),
+            Expression::This(expr) => expr.evaluate(env),
+            Expression::Super(expr) => expr.evaluate(env),
+        };
+        // errors point at the innermost expression that raised them
+        result.map_err(|e| e.with_span(self.span()))
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Binary(expr) => expr.span,
+            Expression::Grouping(expr) => expr.span,
+            Expression::Unary(expr) => expr.span,
+            Expression::Call(expr) => expr.span,
+            Expression::Literal(expr) => expr.span,
+            Expression::Logical(expr) => expr.span,
+            Expression::Var(expr) => expr.span,
+            Expression::Assignment(expr) => expr.span,
+            Expression::Get(expr) => expr.span,
+            Expression::Set(expr) => expr.span,
+            Expression::This(expr) => expr.span,
+            Expression::Super(expr) => expr.span,
         }
     }
 
@@ -159,7 +343,47 @@ impl Expression {
             Expression::Logical(expr) => expr.children(),
             Expression::Var(expr) => expr.children(),
             Expression::Assignment(expr) => expr.children(),
+            Expression::Get(expr) => expr.children(),
+            Expression::Set(expr) => expr.children(),
+            Expression::This(expr) => expr.children(),
+            Expression::Super(expr) => expr.children(),
         }
     }
-}
 
+    pub fn compile(&self, compiler: &mut Compiler) {
+        // code emitted for this node points back at it, not at its parent
+        let enclosing = compiler.set_span(self.span());
+        match self {
+            Expression::Binary(expr) => expr.compile(compiler),
+            Expression::Grouping(expr) => expr.compile(compiler),
+            Expression::Unary(expr) => expr.compile(compiler),
+            Expression::Call(expr) => expr.compile(compiler),
+            Expression::Literal(expr) => expr.compile(compiler),
+            Expression::Logical(expr) => expr.compile(compiler),
+            Expression::Var(expr) => expr.compile(compiler),
+            Expression::Assignment(expr) => expr.compile(compiler),
+            Expression::Get(expr) => expr.compile(compiler),
+            Expression::Set(expr) => expr.compile(compiler),
+            Expression::This(expr) => expr.compile(compiler),
+            Expression::Super(expr) => expr.compile(compiler),
+        }
+        compiler.set_span(enclosing);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        match self {
+            Expression::Binary(expr) => expr.resolve(resolver),
+            Expression::Grouping(expr) => expr.resolve(resolver),
+            Expression::Unary(expr) => expr.resolve(resolver),
+            Expression::Call(expr) => expr.resolve(resolver),
+            Expression::Literal(expr) => expr.resolve(resolver),
+            Expression::Logical(expr) => expr.resolve(resolver),
+            Expression::Var(expr) => expr.resolve(resolver),
+            Expression::Assignment(expr) => expr.resolve(resolver),
+            Expression::Get(expr) => expr.resolve(resolver),
+            Expression::Set(expr) => expr.resolve(resolver),
+            Expression::This(expr) => expr.resolve(resolver),
+            Expression::Super(expr) => expr.resolve(resolver),
+        }
+    }
+}
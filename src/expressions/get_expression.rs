@@ -0,0 +1,38 @@
+use crate::{class::Instance, environment::Environment, interpreter::EvaluationError, resolver::Resolver, tokens::Span, interner::Symbol, vm::{chunk::OpCode, compiler::Compiler}};
+
+use super::expressions::{Expression, ExpressionResult};
+
+
+#[derive(Debug, Clone)]
+pub struct GetExpression {
+    pub object: Box<Expression>,
+    pub name: Symbol,
+    pub span: Span,
+}
+
+impl GetExpression {
+    pub fn new(object: Box<Expression>, name: Symbol, span: Span) -> Self {
+        Self { object, name, span }
+    }
+
+    pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
+        match self.object.evaluate(env)? {
+            ExpressionResult::Instance(instance) => Instance::get(&instance, self.name),
+            _ => Err(EvaluationError::runtime_error("Only instances have properties.".to_string())),
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.object.compile(compiler);
+        let name = compiler.identifier_constant(self.name.as_str());
+        compiler.emit_op_short(OpCode::GetProperty, name);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.object.resolve(resolver);
+    }
+
+    pub fn children(&self) -> Vec<&Expression> {
+        vec![&self.object]
+    }
+}
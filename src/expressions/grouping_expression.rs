@@ -1,19 +1,28 @@
-use crate::{parser::Literal, interpreter::EvaluationError, environment::Environment};
+use crate::{parser::Literal, interpreter::EvaluationError, environment::Environment, resolver::Resolver, tokens::Span, vm::compiler::Compiler};
 use super::expressions::{Expression, ExpressionResult};
 
 #[derive(Debug, Clone)]
 pub struct GroupingExpression {
-    child: Box<Expression>,
+    pub child: Box<Expression>,
+    pub span: Span,
 }
 
 impl GroupingExpression {
-    pub fn new(child: Box<Expression>) -> Self {
-        Self { child }
+    pub fn new(child: Box<Expression>, span: Span) -> Self {
+        Self { child, span }
     }
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
         self.child.evaluate(env)
     }
 
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.child.compile(compiler);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.child.resolve(resolver);
+    }
+
     pub fn children(&self) -> Vec<&Expression> {
         vec![&self.child]
     }
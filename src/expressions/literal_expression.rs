@@ -1,21 +1,34 @@
-use crate::{tokens::{Token, TokenType}, parser::Literal, interpreter::EvaluationError, environment::Environment};
+use crate::{tokens::{Span, Token, TokenType}, parser::Literal, interpreter::EvaluationError, environment::Environment, resolver::Resolver, vm::{chunk::OpCode, compiler::Compiler, value::Value}};
 use super::expressions::{Expression, ExpressionResult};
 
 
 #[derive(Debug, Clone)]
 pub struct LiteralExpression {
-    value: Literal,
+    pub value: Literal,
+    pub span: Span,
 }
 
 impl LiteralExpression {
-    pub fn new(value: Literal) -> Self {
-        Self { value }
+    pub fn new(value: Literal, span: Span) -> Self {
+        Self { value, span }
     }
 
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
         Ok(ExpressionResult::Literal(self.value.clone()))
     }
 
+    pub fn compile(&self, compiler: &mut Compiler) {
+        match &self.value {
+            Literal::Number(n) => compiler.emit_constant(Value::number(*n)),
+            Literal::String(s) => compiler.emit_string(s),
+            Literal::Boolean(true) => compiler.emit_op(OpCode::True),
+            Literal::Boolean(false) => compiler.emit_op(OpCode::False),
+            Literal::Nil => compiler.emit_op(OpCode::Nil),
+        }
+    }
+
+    pub fn resolve(&mut self, _resolver: &mut Resolver) {}
+
     pub fn children(&self) -> Vec<&Expression> {
         vec![]
     }
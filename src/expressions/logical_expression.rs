@@ -1,4 +1,4 @@
-use crate::{tokens::TokenType, environment::Environment, parser::Literal, interpreter::EvaluationError};
+use crate::{tokens::{Span, TokenType}, environment::Environment, parser::Literal, interpreter::EvaluationError, resolver::Resolver, vm::{chunk::OpCode, compiler::Compiler}};
 
 use super::expressions::{Expression, ExpressionResult};
 
@@ -13,11 +13,12 @@ pub struct LogicalExpression {
     pub left: Box<Expression>,
     pub right: Box<Expression>,
     pub operator: LogicalExpressionOperator,
+    pub span: Span,
 }
 
 impl LogicalExpression {
-    pub fn new(left:Box<Expression>, right: Box<Expression>, operator: LogicalExpressionOperator) -> Self {
-        Self { left, right, operator }
+    pub fn new(left:Box<Expression>, right: Box<Expression>, operator: LogicalExpressionOperator, span: Span) -> Self {
+        Self { left, right, operator, span }
     }
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
         let left = self.left.evaluate(env)?;
@@ -37,6 +38,34 @@ impl LogicalExpression {
         Ok(ExpressionResult::Literal(Literal::Boolean(right.is_truthy())))
     }
 
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.left.compile(compiler);
+        match self.operator {
+            LogicalExpressionOperator::And => {
+                let end = compiler.emit_jump(OpCode::JumpIfFalse);
+                compiler.emit_op(OpCode::Pop);
+                self.right.compile(compiler);
+                compiler.patch_jump(end);
+            }
+            LogicalExpressionOperator::Or => {
+                let else_jump = compiler.emit_jump(OpCode::JumpIfFalse);
+                let end = compiler.emit_jump(OpCode::Jump);
+                compiler.patch_jump(else_jump);
+                compiler.emit_op(OpCode::Pop);
+                self.right.compile(compiler);
+                compiler.patch_jump(end);
+            }
+        }
+        // logical operators produce booleans, not their operands
+        compiler.emit_op(OpCode::Not);
+        compiler.emit_op(OpCode::Not);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.left.resolve(resolver);
+        self.right.resolve(resolver);
+    }
+
     pub fn children(&self) -> Vec<&Expression> {
         vec![&self.left, &self.right]
     }
@@ -6,4 +6,8 @@ pub mod var_expression;
 pub mod assignment_expression;
 pub mod logical_expression;
 pub mod call_expression;
+pub mod get_expression;
+pub mod set_expression;
+pub mod this_expression;
+pub mod super_expression;
 pub mod expressions;
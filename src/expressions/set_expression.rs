@@ -0,0 +1,47 @@
+use crate::{environment::Environment, interpreter::EvaluationError, resolver::Resolver, tokens::Span, interner::Symbol, vm::{chunk::OpCode, compiler::Compiler}};
+
+use super::expressions::{Expression, ExpressionResult};
+
+
+#[derive(Debug, Clone)]
+pub struct SetExpression {
+    pub object: Box<Expression>,
+    pub name: Symbol,
+    pub value: Box<Expression>,
+    pub span: Span,
+}
+
+impl SetExpression {
+    pub fn new(object: Box<Expression>, name: Symbol, value: Box<Expression>, span: Span) -> Self {
+        Self { object, name, value, span }
+    }
+
+    pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
+        let object = self.object.evaluate(env)?;
+        // the value runs before the object is checked, as on the VM
+        let value = self.value.evaluate(env)?;
+        match object {
+            ExpressionResult::Instance(instance) => {
+                instance.borrow_mut().set(self.name, value.clone());
+                Ok(value)
+            }
+            _ => Err(EvaluationError::runtime_error("Only instances have fields.".to_string())),
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.object.compile(compiler);
+        self.value.compile(compiler);
+        let name = compiler.identifier_constant(self.name.as_str());
+        compiler.emit_op_short(OpCode::SetProperty, name);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.value.resolve(resolver);
+        self.object.resolve(resolver);
+    }
+
+    pub fn children(&self) -> Vec<&Expression> {
+        vec![&self.object, &self.value]
+    }
+}
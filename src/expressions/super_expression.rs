@@ -0,0 +1,60 @@
+use crate::{environment::Environment, interpreter::EvaluationError, resolver::{ClassType, Resolver, Slot}, tokens::Span, interner::Symbol, vm::{chunk::OpCode, compiler::Compiler}};
+
+use super::expressions::{Callable, Expression, ExpressionResult};
+
+
+#[derive(Debug, Clone)]
+pub struct SuperExpression {
+    pub method: Symbol,
+    // the class's bound `super`
+    slot: Option<Slot>,
+    pub span: Span,
+}
+
+impl SuperExpression {
+    pub fn new(method: Symbol, span: Span) -> Self {
+        Self { method, slot: None, span }
+    }
+
+    pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
+        let slot = match self.slot {
+            Some(slot) => slot,
+            None => return Err(EvaluationError::runtime_error("Can't use 'super' outside of a class.".to_string())),
+        };
+
+        let superclass = match env.get_at(slot) {
+            ExpressionResult::Callable(Callable::Class(class)) => class,
+            _ => return Err(EvaluationError::runtime_error("Superclass must be a class.".to_string())),
+        };
+        // `this` is always the only local of the scope just inside the one
+        // holding `super`
+        let instance = match env.get_at(Slot { depth: slot.depth - 1, index: 0 }) {
+            ExpressionResult::Instance(instance) => instance,
+            _ => return Err(EvaluationError::runtime_error("Can't use 'super' outside of a method.".to_string())),
+        };
+
+        match superclass.find_method(self.method) {
+            Some(method) => Ok(ExpressionResult::Callable(Callable::UserDefined(method.bind(instance)))),
+            None => Err(EvaluationError::runtime_error(format!("Undefined property '{}'.", self.method))),
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        compiler.get_variable("this");
+        compiler.get_variable("super");
+        let name = compiler.identifier_constant(self.method.as_str());
+        compiler.emit_op_short(OpCode::GetSuper, name);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        match resolver.current_class() {
+            ClassType::None => resolver.error("Can't use 'super' outside of a class.", self.span),
+            ClassType::Class => resolver.error("Can't use 'super' in a class with no superclass.", self.span),
+            ClassType::Subclass => self.slot = resolver.resolve_local(Symbol::intern("super")),
+        }
+    }
+
+    pub fn children(&self) -> Vec<&Expression> {
+        vec![]
+    }
+}
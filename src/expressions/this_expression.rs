@@ -0,0 +1,40 @@
+use crate::{environment::Environment, interpreter::EvaluationError, resolver::{Resolver, Slot}, tokens::Span, interner::Symbol, vm::compiler::Compiler};
+
+use super::expressions::{Expression, ExpressionResult};
+
+
+#[derive(Debug, Clone)]
+pub struct ThisExpression {
+    // the method's bound `this`
+    slot: Option<Slot>,
+    pub span: Span,
+}
+
+impl ThisExpression {
+    pub fn new(span: Span) -> Self {
+        Self { slot: None, span }
+    }
+
+    pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
+        match self.slot {
+            Some(slot) => Ok(env.get_at(slot)),
+            None => Err(EvaluationError::runtime_error("Can't use 'this' outside of a class.".to_string())),
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        compiler.get_variable("this");
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        if !resolver.in_class() {
+            resolver.error("Can't use 'this' outside of a class.", self.span);
+            return;
+        }
+        self.slot = resolver.resolve_local(Symbol::intern("this"));
+    }
+
+    pub fn children(&self) -> Vec<&Expression> {
+        vec![]
+    }
+}
@@ -1,16 +1,17 @@
-use crate::{tokens::{Token, TokenType}, parser::Literal, interpreter::EvaluationError, environment::Environment, expressions::expressions::ExpressionResult};
+use crate::{tokens::{Span, Token, TokenType}, parser::Literal, interpreter::EvaluationError, environment::Environment, expressions::expressions::ExpressionResult, resolver::Resolver, vm::{chunk::OpCode, compiler::Compiler}};
 use super::expressions::Expression;
 
 
 #[derive(Debug, Clone)]
 pub struct UnaryExpression {
-    op: Token,
-    child: Box<Expression>,
+    pub op: Token,
+    pub child: Box<Expression>,
+    pub span: Span,
 }
 
 impl UnaryExpression {
-    pub fn new(op: Token, child: Box<Expression>) -> Self {
-        Self { op, child }
+    pub fn new(op: Token, child: Box<Expression>, span: Span) -> Self {
+        Self { op, child, span }
     }
 
     pub fn evaluate(&self, env: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
@@ -21,22 +22,30 @@ impl UnaryExpression {
                     return Ok(ExpressionResult::Literal(Literal::Number(-n)));
                 }
 
-                panic!("Expected number, got: {:?}", child);
+                Err(EvaluationError::runtime_error(format!("Expected number, got: {}", child.describe())))
             }
-            TokenType::Bang => {
-                return match child {
-                    ExpressionResult::Literal(Literal::Boolean(b)) => Ok(ExpressionResult::Literal(Literal::Boolean(!b))),
-                    ExpressionResult::Literal(Literal::Number(n)) => Ok(ExpressionResult::Literal(Literal::Boolean(n == 0.0))),
-                    ExpressionResult::Literal(Literal::String(s)) => Ok(ExpressionResult::Literal(Literal::Boolean(s.len() == 0))),
-                    _ => Err(EvaluationError::runtime_error("Expected boolean, number or string".to_string()))
-                };
+            TokenType::Bang => Ok(ExpressionResult::Literal(Literal::Boolean(!child.is_truthy()))),
+            _ => {
+                panic!("Unexpected token: {:?}", self.op);
             }
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        self.child.compile(compiler);
+        match &self.op.token_type {
+            TokenType::Minus => compiler.emit_op(OpCode::Negate),
+            TokenType::Bang => compiler.emit_op(OpCode::Not),
             _ => {
                 panic!("Unexpected token: {:?}", self.op);
             }
         }
     }
 
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        self.child.resolve(resolver);
+    }
+
     pub fn children(&self) -> Vec<&Expression> {
         vec![&self.child]
     }
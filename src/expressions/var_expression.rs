@@ -1,22 +1,39 @@
 use std::{rc::{self, Rc}, cell::RefCell};
 
-use crate::{environment::Environment, parser::Literal, interpreter::EvaluationError};
+use crate::{environment::Environment, parser::Literal, interpreter::EvaluationError, resolver::{Resolver, Slot}, tokens::Span, interner::Symbol, vm::compiler::Compiler};
 
 use super::expressions::{Expression, ExpressionResult};
 
 
 #[derive(Debug, Clone)]
 pub struct VarExpression {
-    pub name: String,
+    pub name: Symbol,
+    // where the declaration lives, None for globals
+    slot: Option<Slot>,
+    pub span: Span,
 }
 
 
 impl VarExpression {
-    pub fn new(name: String) -> Self {
-        Self { name }
+    pub fn new(name: Symbol, span: Span) -> Self {
+        Self { name, slot: None, span }
     }
     pub fn evaluate(&self, environment: &mut Environment) -> Result<ExpressionResult, EvaluationError> {
-        environment.get(&self.name)
+        match self.slot {
+            Some(slot) => Ok(environment.get_at(slot)),
+            None => environment.get_global(self.name),
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        compiler.get_variable(self.name.as_str());
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        if resolver.is_declaring(self.name) {
+            resolver.error(&format!("Can't read local variable '{}' in its own initializer.", self.name), self.span);
+        }
+        self.slot = resolver.resolve_local(self.name);
     }
     pub fn children(&self) -> Vec<&Expression> {
         vec![]
@@ -0,0 +1,308 @@
+use crate::{
+    scanner::Scanner,
+    tokens::{Span, Token, TokenType},
+};
+
+const INDENT: &str = "  ";
+
+/// Prints Lox source back out in one consistent style. It works from the
+/// tokens rather than the tree, so comments are kept and `for` loops stay
+/// `for` loops. Up to one blank line between statements is kept as well.
+/// `source` should already parse; this doesn't check.
+pub fn format(source: &str) -> String {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().clone();
+    let mut pieces: Vec<Piece> = tokens.iter().filter(|token| token.token_type != TokenType::EOF).map(Piece::Token).collect();
+    pieces.extend(scanner.comments().iter().map(|span| Piece::Comment(*span)));
+    pieces.sort_by_key(|piece| piece.span().start);
+
+    let mut formatter = Formatter {
+        source,
+        out: String::new(),
+        depth: 0,
+        parens: 0,
+        braces: vec![],
+        line_break: true,
+        blank: false,
+        line_first: None,
+        last_token: None,
+        last_line: 0,
+        after_comment: false,
+        after_comment_line: false,
+        after_unary: false,
+    };
+    for i in 0..pieces.len() {
+        formatter.piece(&pieces, i);
+    }
+    if !formatter.out.is_empty() {
+        formatter.out.push('\n');
+    }
+    formatter.out
+}
+
+enum Piece<'a> {
+    Token(&'a Token),
+    Comment(Span),
+}
+
+impl Piece<'_> {
+    fn span(&self) -> Span {
+        match self {
+            Piece::Token(token) => token.span,
+            Piece::Comment(span) => *span,
+        }
+    }
+}
+
+// what a pair of braces holds, which decides the blank lines around it
+#[derive(Clone, Copy, PartialEq)]
+enum Brace {
+    Block,
+    Class,
+    Function,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    depth: usize,
+    // inside parentheses, as in a `for` clause, `;` doesn't end the line
+    parens: usize,
+    braces: Vec<Brace>,
+    // the next piece starts a new line
+    line_break: bool,
+    // the next line is set apart by a blank one, after a declaration
+    blank: bool,
+    // what the line being written starts with
+    line_first: Option<&'a TokenType>,
+    last_token: Option<&'a TokenType>,
+    // the source line the last piece ended on
+    last_line: usize,
+    after_comment: bool,
+    // the last piece was a comment with a line of its own
+    after_comment_line: bool,
+    after_unary: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn piece(&mut self, pieces: &[Piece<'a>], i: usize) {
+        let span = pieces[i].span();
+        let text = &self.source[span.start..span.end];
+        match pieces[i] {
+            Piece::Comment(_) => {
+                let own_line = span.line > self.last_line;
+                if own_line {
+                    // a comment right above a declaration goes with it
+                    let next = pieces[i + 1..].iter().find_map(|piece| match piece {
+                        Piece::Token(token) => Some(&token.token_type),
+                        Piece::Comment(_) => None,
+                    });
+                    let declaration = !self.after_comment_line && next.is_some_and(|next| self.starts_declaration(next));
+                    self.new_line(span.line, declaration, false);
+                    self.line_first = None;
+                } else {
+                    // a trailing comment stays at the end of its line
+                    self.out.push(' ');
+                }
+                let text = text.trim_end();
+                self.out.push_str(text);
+                let end_line = span.line + text.matches('\n').count();
+                let next_line = pieces.get(i + 1).map(|piece| piece.span().line);
+                if text.starts_with("//") || next_line.is_some_and(|line| line > end_line) {
+                    self.line_break = true;
+                }
+                self.last_line = end_line;
+                self.after_comment = true;
+                self.after_comment_line = own_line;
+            }
+            Piece::Token(token) => {
+                self.token(token, text);
+                self.last_line = span.line + text.matches('\n').count();
+                self.after_comment = false;
+                self.after_comment_line = false;
+            }
+        }
+    }
+
+    fn token(&mut self, token: &'a Token, text: &str) {
+        let kind = &token.token_type;
+        let mut closing = false;
+        match kind {
+            // `else` stays on the line that ends its `if`
+            TokenType::Else if !self.after_comment => self.line_break = false,
+            TokenType::RightBrace => {
+                self.depth -= 1;
+                // an empty block stays on one line
+                closing = self.last_token != Some(&TokenType::LeftBrace) || self.after_comment;
+                self.line_break = closing;
+            }
+            _ => {}
+        }
+
+        if self.line_break {
+            let declaration = !self.after_comment_line && self.starts_declaration(kind);
+            self.new_line(token.span.line, declaration, closing);
+            self.line_first = Some(kind);
+        } else if self.spaced(kind) {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+
+        self.after_unary = match kind {
+            TokenType::Bang => true,
+            TokenType::Minus => !self.last_token.is_some_and(ends_value),
+            _ => false,
+        };
+        match kind {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => self.parens -= 1,
+            TokenType::LeftBrace => {
+                let brace = match self.line_first {
+                    Some(TokenType::Fun) => Brace::Function,
+                    Some(TokenType::Class) => Brace::Class,
+                    Some(TokenType::Idenfitier(_)) if self.braces.last() == Some(&Brace::Class) => Brace::Function,
+                    _ => Brace::Block,
+                };
+                self.braces.push(brace);
+                self.depth += 1;
+                self.line_break = true;
+            }
+            TokenType::RightBrace => {
+                self.blank = self.braces.pop() != Some(Brace::Block);
+                self.line_break = true;
+            }
+            TokenType::Semicolon if self.parens == 0 => self.line_break = true,
+            _ => {}
+        }
+        self.last_token = Some(kind);
+    }
+
+    // whether a line starting with `kind` begins a function or class
+    fn starts_declaration(&self, kind: &TokenType) -> bool {
+        match kind {
+            TokenType::Fun | TokenType::Class => self.parens == 0,
+            // class bodies hold nothing but methods
+            TokenType::Idenfitier(_) => self.braces.last() == Some(&Brace::Class),
+            _ => false,
+        }
+    }
+
+    // starts a line for something from source line `line`, keeping a blank
+    // line from the source or setting a declaration apart, but never at the
+    // start of a block or before its end
+    fn new_line(&mut self, line: usize, declaration: bool, closing: bool) {
+        let opened = self.last_token == Some(&TokenType::LeftBrace) && !self.after_comment;
+        let blank = (line > self.last_line + 1 || declaration || self.blank) && !closing && !opened && !self.out.is_empty();
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        if blank {
+            self.out.push('\n');
+        }
+        self.out.push_str(&INDENT.repeat(self.depth));
+        self.line_break = false;
+        self.blank = false;
+    }
+
+    // whether `kind` needs a space between it and what is already on the line
+    fn spaced(&self, kind: &TokenType) -> bool {
+        if self.after_comment {
+            return true;
+        }
+        let Some(last) = self.last_token else { return false };
+        if self.after_unary || matches!(last, TokenType::LeftParen | TokenType::Dot) {
+            return false;
+        }
+        match kind {
+            // a `}` that doesn't start a line closes an empty block
+            TokenType::RightParen | TokenType::Comma | TokenType::Semicolon | TokenType::Dot | TokenType::RightBrace => false,
+            // calls and declarations, but not `if (` or `a + (`
+            TokenType::LeftParen => !matches!(last, TokenType::Idenfitier(_) | TokenType::RightParen),
+            _ => true,
+        }
+    }
+}
+
+// whether a token can end an operand, making a `-` after it binary
+fn ends_value(kind: &TokenType) -> bool {
+    matches!(
+        kind,
+        TokenType::Idenfitier(_)
+            | TokenType::String(_)
+            | TokenType::Number(_)
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+            | TokenType::RightParen
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        assert!(crate::parse_syntax(source).is_ok(), "{}", source);
+        format(source)
+    }
+
+    #[test]
+    fn it_normalizes_layout() {
+        let source = "var a=1;fun add(x,y){return x+y;}\nclass B<A{init(n){this.n=n;}get(){return super.get()*-(1+2);}}\nif(a>0)print \"pos\";else{print nil or !true;}";
+        assert_eq!(
+            fmt(source),
+            "\
+var a = 1;
+
+fun add(x, y) {
+  return x + y;
+}
+
+class B < A {
+  init(n) {
+    this.n = n;
+  }
+
+  get() {
+    return super.get() * -(1 + 2);
+  }
+}
+
+if (a > 0) print \"pos\"; else {
+  print nil or !true;
+}
+"
+        );
+    }
+
+    #[test]
+    fn it_keeps_comments_and_for_loops() {
+        let source = "// counts\nfor(var i=0;i<3;i=i+1){print i; // each\n\n\n/* done? */ print i-1;}\n{}\nfor (;;) break;\n/* doc */\nfun f() {} // after";
+        assert_eq!(
+            fmt(source),
+            "\
+// counts
+for (var i = 0; i < 3; i = i + 1) {
+  print i; // each
+
+  /* done? */ print i - 1;
+}
+{}
+for (;;) break;
+
+/* doc */
+fun f() {} // after
+"
+        );
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        for script in std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts")).unwrap() {
+            let source = std::fs::read_to_string(script.unwrap().path()).unwrap();
+            let once = fmt(&source);
+            assert_eq!(fmt(&once), once);
+        }
+    }
+}
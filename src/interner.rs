@@ -0,0 +1,106 @@
+use std::{cell::RefCell, collections::HashMap, fmt::{self, Debug, Display, Formatter}, marker::PhantomData};
+
+/// An interned name. Every occurrence of the same text gets the same
+/// symbol, so symbols compare and hash as cheaply as an integer and copying
+/// one never allocates.
+///
+/// Each thread has its own interner, so a symbol can't be sent to another
+/// thread, where it would mean something else:
+///
+/// ```compile_fail
+/// let symbol = lox_rust::interner::Symbol::intern("name");
+/// std::thread::spawn(move || symbol.as_str());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol {
+    id: u32,
+    // raw pointers are neither `Send` nor `Sync`
+    thread: PhantomData<*const ()>,
+}
+
+/// Maps text to symbols and back. Interned strings are never freed, so only
+/// the names in a program's source are interned, never strings it builds
+/// while running.
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+impl Interner {
+    fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(s) {
+            return *symbol;
+        }
+        let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+        let symbol = Symbol { id: self.strings.len() as u32, thread: PhantomData };
+        self.strings.push(s);
+        self.symbols.insert(s, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(s))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().strings[self.id as usize])
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_gives_the_same_text_the_same_symbol() {
+        assert_eq!(Symbol::intern("name"), Symbol::intern(&format!("na{}", "me")));
+        assert_ne!(Symbol::intern("name"), Symbol::intern("other"));
+    }
+
+    #[test]
+    fn it_returns_the_interned_text() {
+        assert_eq!(Symbol::intern("hello").as_str(), "hello");
+        assert_eq!(Symbol::intern("").to_string(), "");
+    }
+
+    #[test]
+    fn strings_built_at_runtime_are_not_interned() {
+        let interned = || INTERNER.with(|interner| interner.borrow().strings.len());
+        let before = interned();
+        crate::Interpreter::new().eval("var i = 0; while (i < 100) { var s = \"w\" + i; i = i + 1; }").unwrap();
+        // just the names in the source, and the interpreter's own
+        assert!(interned() - before < 20);
+    }
+
+    #[test]
+    fn list_indices_are_not_interned() {
+        use crate::convert::{FromLox, IntoLox};
+
+        let interned = || INTERNER.with(|interner| interner.borrow().strings.len());
+        // the class and `length` names are interned once, up front
+        vec![0].into_lox();
+        let before = interned();
+        let list = (0..1000).rev().collect::<Vec<i32>>().into_lox();
+        assert_eq!(Vec::<i32>::from_lox(&list).unwrap()[0], 999);
+        assert_eq!(interned(), before);
+    }
+}
@@ -1,22 +1,254 @@
+use std::{io::Write, rc::Rc};
+
+use crate::{
+    collector::Collector,
+    convert::TypedNative,
+    diagnostics::Diagnostic,
+    environment::Environment,
+    expressions::expressions::{Callable, ExpressionResult, NativeFunction},
+    interner::Symbol,
+    limits::{Limits, Meter},
+    output::Sink,
+    parser::Literal,
+    statement::Statement,
+    tokens::Span,
+    vm::heap::{GcConfig, GcStats},
+};
+
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ErrorType {
     RuntimeError,
-    BreakError
+    BreakError,
+    // raised when a run exceeds its `Limits`
+    StepLimitExceeded,
+    CallDepthExceeded,
+    Timeout,
+}
+
+/// A function call the error unwound through: the function that was called
+/// and where it was called from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub function: String,
+    pub call_site: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct EvaluationError {
     pub kind: ErrorType,
     pub message: String,
+    pub span: Option<Span>,
+    // innermost call first, filled in as the error propagates out of calls
+    pub trace: Vec<CallFrame>,
 }
 
 impl EvaluationError {
     pub fn runtime_error(message: String) -> Self {
-        Self { message, kind: ErrorType::RuntimeError }
+        Self { message, kind: ErrorType::RuntimeError, span: None, trace: vec![] }
     }
 
     pub fn break_error() -> Self {
-        Self { kind: ErrorType::BreakError, message: "Unexpected break statement".to_string() }
+        Self { kind: ErrorType::BreakError, message: "Unexpected break statement".to_string(), span: None, trace: vec![] }
+    }
+
+    /// Records that the error propagated out of a call to `function` made at `call_site`.
+    pub fn in_call(mut self, function: String, call_site: Span) -> Self {
+        self.trace.push(CallFrame { function, call_site });
+        self
+    }
+
+    /// The Lox-level backtrace, innermost frame first: each function on the
+    /// stack paired with the location it was executing when the error occurred.
+    pub fn backtrace(&self) -> Vec<(String, Option<Span>)> {
+        let mut frames = vec![];
+        let mut location = self.span;
+        for frame in &self.trace {
+            frames.push((frame.function.clone(), location));
+            location = Some(frame.call_site);
+        }
+        frames.push(("<script>".to_string(), location));
+        frames
+    }
+
+    /// Attaches `span` unless the error already points somewhere more specific.
+    pub fn with_span(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}", self.message, span),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Runs Lox source on the tree-walker, keeping its globals between calls so
+/// a host program can define functions in one `eval` and call them later.
+pub struct Interpreter {
+    environment: Environment,
+    diagnostics: Sink,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_gc(GcConfig::default())
+    }
+
+    /// An interpreter whose collector runs as `config` says.
+    pub fn with_gc(config: GcConfig) -> Interpreter {
+        let mut interpreter = Interpreter { environment: Environment::with_gc(config), diagnostics: Sink::stderr() };
+        interpreter.define_native("clock", 0, |_| {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap();
+            Ok(ExpressionResult::Literal(Literal::Number(now.as_secs() as f64)))
+        });
+        interpreter
+    }
+
+    /// Scans, parses, resolves and runs `source`. If it ends with an
+    /// expression statement, that expression's value is returned, otherwise nil.
+    pub fn eval(&mut self, source: &str) -> Result<ExpressionResult, Vec<Diagnostic>> {
+        let statements = crate::parse(source)?;
+        self.execute(statements).map_err(|e| vec![Diagnostic::from(&e)])
+    }
+
+    /// Runs statements that have already been resolved, stopping at the
+    /// first runtime error. Each call gets the full budget of the limits.
+    pub fn execute(&mut self, statements: Vec<Box<Statement>>) -> Result<ExpressionResult, EvaluationError> {
+        self.environment.meter().restart();
+        let mut result = ExpressionResult::Literal(Literal::Nil);
+        for statement in statements {
+            result = match *statement {
+                Statement::Expression(statement) => statement.expression.evaluate(&mut self.environment)?,
+                statement => {
+                    statement.execute(&mut self.environment)?;
+                    ExpressionResult::Literal(Literal::Nil)
+                }
+            };
+        }
+        Ok(result)
+    }
+
+    /// Bounds the work done by every later `eval`, `execute` or
+    /// `call_function`. Exceeding a limit raises an error whose kind says
+    /// which one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        *self.environment.meter() = Meter::new(limits);
+    }
+
+    /// Sends what `print` writes to `writer` instead of stdout.
+    pub fn set_output(&mut self, writer: impl Write + 'static) {
+        self.environment.output().redirect(writer);
+    }
+
+    /// Where `print` writes.
+    pub fn output(&self) -> Sink {
+        self.environment.output()
+    }
+
+    /// Where `report` writes.
+    pub fn diagnostics(&self) -> Sink {
+        self.diagnostics.clone()
+    }
+
+    /// Sends what `report` writes to `writer` instead of stderr.
+    pub fn set_diagnostics(&mut self, writer: impl Write + 'static) {
+        self.diagnostics.redirect(writer);
+    }
+
+    /// Writes `diagnostic`, rendered against the source it came from, to
+    /// the diagnostics sink.
+    pub fn report(&mut self, source_name: &str, source: &str, diagnostic: &Diagnostic) {
+        // there is nowhere left to report a failure to report
+        let _ = write!(self.diagnostics, "{}", diagnostic.render(source_name, source));
+    }
+
+    /// Calls the global function or class `name`.
+    pub fn call_function(&mut self, name: &str, args: Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError> {
+        let callable = match self.environment.get_global(Symbol::intern(name))? {
+            ExpressionResult::Callable(callable) => callable,
+            value => return Err(EvaluationError::runtime_error(format!("Can only call functions, not {}", value.describe()))),
+        };
+        if args.len() != callable.arity() {
+            return Err(EvaluationError::runtime_error(format!("Expected {} arguments but got {}", callable.arity(), args.len())));
+        }
+        self.environment.meter().restart();
+        callable.call(args)
+    }
+
+    /// Frees everything only kept alive by reference cycles, such as
+    /// closures that captured the scope they are stored in. This also
+    /// happens on its own as the interpreter allocates.
+    pub fn collect_garbage(&mut self) -> usize {
+        Collector::collect(&self.environment.collector())
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.environment.collector().borrow().stats()
+    }
+
+    /// Forgets every global, including natives the host defined, but keeps
+    /// the sinks, limits and collector settings.
+    pub fn reset(&mut self) {
+        let (output, diagnostics, limits) = (self.output(), self.diagnostics(), self.environment.meter().limits());
+        let gc = self.environment.collector().borrow().config();
+        *self = Interpreter::with_gc(gc);
+        self.environment.set_output(output);
+        self.diagnostics = diagnostics;
+        self.set_limits(limits);
+    }
+
+    /// Every global and its value, sorted by name.
+    pub fn globals(&self) -> Vec<(String, ExpressionResult)> {
+        self.environment.globals().into_iter().map(|(name, value)| (name.to_string(), value)).collect()
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<ExpressionResult> {
+        self.environment.get_global(Symbol::intern(name)).ok()
+    }
+
+    /// Defines the global `name`, replacing any existing value.
+    pub fn set_global(&mut self, name: &str, value: ExpressionResult) {
+        self.environment.define(Symbol::intern(name), value);
+    }
+
+    /// Like `define_native`, but with arguments and result converted to and
+    /// from Rust types, e.g. `define_function("add", |a: f64, b: f64| a + b)`.
+    /// Arguments of the wrong type raise a runtime error naming the argument.
+    pub fn define_function<Args, F: TypedNative<Args>>(&mut self, name: &str, function: F) {
+        let arity = function.arity();
+        let native = NativeFunction::new(Symbol::intern(name), arity, function.into_native(Symbol::intern(name)));
+        self.set_global(name, ExpressionResult::Callable(Callable::Native(native)));
+    }
+
+    /// Makes `function` callable from Lox as the global `name`.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(Vec<ExpressionResult>) -> Result<ExpressionResult, EvaluationError> + 'static,
+    {
+        let native = NativeFunction::new(Symbol::intern(name), arity, Rc::new(function));
+        self.set_global(name, ExpressionResult::Callable(Callable::Native(native)));
+    }
+}
+
+impl Drop for Interpreter {
+    // functions keep the globals alive through their closures, so the
+    // interpreter's own handle has to go before those cycles can be
+    // collected. A function the host still holds keeps them working.
+    fn drop(&mut self) {
+        self.environment.release_globals();
+        self.collect_garbage();
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
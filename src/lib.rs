@@ -0,0 +1,74 @@
+//! A Lox interpreter that can be embedded in other Rust programs.
+//!
+//! ```
+//! use lox_rust::{FromLox, Interpreter, IntoLox};
+//!
+//! let mut lox = Interpreter::new();
+//! lox.define_function("double", |n: f64| n * 2.0);
+//! lox.eval("fun add(a, b) { return double(a) + b; }").unwrap();
+//!
+//! let result = lox.call_function("add", vec![1.into_lox(), 2.into_lox()]).unwrap();
+//! assert_eq!(f64::from_lox(&result).unwrap(), 4.0);
+//! ```
+
+pub mod tokens;
+pub mod scanner;
+pub mod diagnostics;
+pub mod parser;
+pub mod expressions;
+pub mod interpreter;
+pub mod statement;
+pub mod environment;
+pub mod resolver;
+pub mod class;
+pub mod collector;
+pub mod vm;
+pub mod interner;
+pub mod convert;
+pub mod output;
+pub mod limits;
+pub mod repl;
+pub mod formatter;
+pub mod dump;
+
+pub use convert::{FromLox, IntoLox};
+pub use diagnostics::Diagnostic;
+pub use expressions::expressions::ExpressionResult;
+pub use interpreter::{ErrorType, EvaluationError, Interpreter};
+pub use limits::Limits;
+pub use parser::Literal;
+
+use statement::Statement;
+
+/// Scans, parses and resolves `source` into statements ready for either
+/// backend. Every error found along the way is returned.
+pub fn parse(source: &str) -> Result<Vec<Box<Statement>>, Vec<Diagnostic>> {
+    let mut tree = parse_syntax(source)?;
+    if let Err(errors) = resolver::Resolver::new().resolve(&mut tree) {
+        return Err(errors.iter().map(Diagnostic::from).collect());
+    }
+    Ok(tree)
+}
+
+/// Scans and parses `source` without resolving it, for tools that only need
+/// its shape.
+pub fn parse_syntax(source: &str) -> Result<Vec<Box<Statement>>, Vec<Diagnostic>> {
+    let mut scanner = scanner::Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().clone();
+    let mut diagnostics: Vec<Diagnostic> = scanner.errors().iter().map(Diagnostic::from).collect();
+
+    let mut parser = parser::Parser::new(&tokens);
+    let tree = match parser.parse() {
+        Ok(tree) => tree,
+        Err(errors) => {
+            diagnostics.extend(errors.iter().map(Diagnostic::from));
+            return Err(diagnostics);
+        }
+    };
+    // syntax errors from the scanner are reported before parsing, but still
+    // stop the program from running
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    Ok(tree)
+}
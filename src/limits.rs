@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+
+use crate::interpreter::{ErrorType, EvaluationError};
+
+// checking the clock on every step would cost more than the step itself
+const STEPS_PER_CLOCK_CHECK: u64 = 256;
+
+/// How deeply calls may nest unless told otherwise.
+pub const DEFAULT_CALL_DEPTH: usize = 1000;
+
+/// Bounds on how much work a program may do, so untrusted scripts can't
+/// hang or crash the host. `None` means unbounded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Statements on the tree-walker, instructions on the VM.
+    pub max_steps: Option<u64>,
+    pub max_call_depth: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// No limits at all. Deep recursion then runs until memory runs out, or
+    /// the VM's own ceiling on frames.
+    pub fn none() -> Limits {
+        Limits { max_steps: None, max_call_depth: None, timeout: None }
+    }
+}
+
+impl Default for Limits {
+    /// Only the call depth is limited, the same on both backends.
+    fn default() -> Self {
+        Limits { max_call_depth: Some(DEFAULT_CALL_DEPTH), ..Limits::none() }
+    }
+}
+
+/// Tracks a run's usage against its `Limits`.
+#[derive(Debug, Clone)]
+pub struct Meter {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+}
+
+fn limit_error(kind: ErrorType, message: String) -> EvaluationError {
+    EvaluationError { kind, message, span: None, trace: vec![] }
+}
+
+impl Meter {
+    pub fn new(limits: Limits) -> Meter {
+        let mut meter = Meter { limits, steps: 0, depth: 0, deadline: None };
+        meter.restart();
+        meter
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Gives a new run the full budget: no steps taken, and the timeout
+    /// counting from now.
+    pub fn restart(&mut self) {
+        self.steps = 0;
+        self.depth = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Counts one step, failing once the step budget or the time runs out.
+    pub fn step(&mut self) -> Result<(), EvaluationError> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(limit_error(ErrorType::StepLimitExceeded, format!("Exceeded the limit of {} steps", max)));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(STEPS_PER_CLOCK_CHECK) && Instant::now() >= deadline {
+                let timeout = self.limits.timeout.unwrap_or_default();
+                return Err(limit_error(ErrorType::Timeout, format!("Timed out after {:?}", timeout)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a call, failing if it would nest too deeply. Every successful
+    /// `enter_call` must be paired with an `exit_call`.
+    pub fn enter_call(&mut self) -> Result<(), EvaluationError> {
+        self.check_next_call()?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Fails if one more call would nest too deeply, without counting it.
+    pub fn check_next_call(&self) -> Result<(), EvaluationError> {
+        self.check_call_depth(self.depth)
+    }
+
+    /// Fails if a call made with `depth` calls already in progress would
+    /// nest too deeply, for callers that track the depth themselves.
+    pub fn check_call_depth(&self, depth: usize) -> Result<(), EvaluationError> {
+        match self.limits.max_call_depth {
+            Some(max) if depth >= max => Err(limit_error(
+                ErrorType::CallDepthExceeded,
+                format!("Stack overflow: exceeded the call depth limit of {}", max),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn exit_call(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_stops_after_the_step_budget() {
+        let mut meter = Meter::new(Limits { max_steps: Some(2), ..Limits::none() });
+        assert!(meter.step().is_ok());
+        assert!(meter.step().is_ok());
+        assert_eq!(meter.step().unwrap_err().kind, ErrorType::StepLimitExceeded);
+
+        meter.restart();
+        assert!(meter.step().is_ok());
+    }
+
+    #[test]
+    fn it_limits_nesting_not_total_calls() {
+        let mut meter = Meter::new(Limits { max_call_depth: Some(1), ..Limits::none() });
+        meter.enter_call().unwrap();
+        assert_eq!(meter.enter_call().unwrap_err().kind, ErrorType::CallDepthExceeded);
+        meter.exit_call();
+        assert!(meter.enter_call().is_ok());
+    }
+
+    #[test]
+    fn it_times_out() {
+        let mut meter = Meter::new(Limits { timeout: Some(Duration::ZERO), ..Limits::none() });
+        let error = (0..STEPS_PER_CLOCK_CHECK).find_map(|_| meter.step().err()).unwrap();
+        assert_eq!(error.kind, ErrorType::Timeout);
+    }
+}
@@ -1,89 +1,470 @@
-use std::{rc::Rc, cell::RefCell};
+use std::{io::{IsTerminal, Read}, time::Duration};
 
-use expressions::expressions::{Expression, ExpressionResult, Callable};
-use statement::Statement;
+use lox_rust::{
+    diagnostics::Diagnostic,
+    dump,
+    formatter,
+    scanner::Scanner,
+    statement::Statement,
+    repl::{self, PlainReader, Repl},
+    vm::{self, heap::{GcConfig, ObjRef}, machine::VM},
+    Interpreter, Limits,
+};
 
-mod tokens;
-mod scanner;
-mod error;
-mod parser;
-mod expressions;
-mod interpreter;
-mod statement;
-mod environment;
+// exit codes from sysexits.h, as used by the reference clox and jlox
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
 
-fn interpret(statements: Vec<Box<Statement>>) {
-    let env = &mut environment::Environment::new();
+const USAGE: &str = "\
+Usage: lox-rust [command] [options] [file | -]
 
-    // Define built-in functions
-    env.define("clock".to_string(), ExpressionResult::Callable(Callable::Clock));
+Commands:
+  run          run a script (the default when given a file)
+  repl         start an interactive session (the default without one)
+  check        report errors without running anything
+  tokens       print the tokens the scanner produces
+  ast          print the syntax tree
+  fmt          print the source in the standard layout
+  disassemble  print the bytecode the VM would run
 
-    for statement in statements {
-        if let Err(e) = statement.execute(env) {
-            println!("RuntimeError: {}", e.message);
+Options:
+  -e <code>          take the source from <code> instead of a file
+  -                  take the source from stdin
+  --backend tree|vm  how to run programs (default: tree)
+  --format text|json|sexpr
+                     how tokens and ast print (default: text for tokens,
+                     sexpr for ast)
+  --gc-stress        collect garbage on every allocation
+  --max-steps <n>    stop after <n> statements, or VM instructions
+  --max-depth <n>    stop calls nesting deeper than <n> (default: 1000)
+  --timeout <ms>     stop after <ms> milliseconds
+  -h, --help         show this message
+  --                 take what follows as a file, even if it starts with -";
+
+// diagnostics go to stderr, so they never mix with a program's output
+fn report(source_name: &str, source: &str, diagnostic: Diagnostic) {
+    eprint!("{}", diagnostic.render(source_name, source));
+}
+
+/// How a program is executed once it has been parsed and resolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    // walks the syntax tree directly, collecting the cycles reference
+    // counting misses as configured
+    Tree(GcConfig),
+    // compiles to bytecode and runs it on the stack VM, whose heap is
+    // collected as configured
+    Vm(GcConfig),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Repl,
+    Check,
+    Tokens,
+    Ast,
+    Fmt,
+    Disassemble,
+}
+
+impl Command {
+    fn parse(name: &str) -> Option<Command> {
+        match name {
+            "run" => Some(Command::Run),
+            "repl" => Some(Command::Repl),
+            "check" => Some(Command::Check),
+            "tokens" => Some(Command::Tokens),
+            "ast" => Some(Command::Ast),
+            "fmt" => Some(Command::Fmt),
+            "disassemble" => Some(Command::Disassemble),
+            _ => None,
         }
     }
 }
 
-fn run(source: String) {
-    // create a scanner
-    let mut scanner = scanner::Scanner::new(source);
-    // create a parser
-    let tokens = scanner.scan_tokens();
+/// How `tokens` and `ast` print what they find.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    // `Debug` output, for reading by eye
+    Text,
+    Dump(dump::Format),
+}
 
+/// Where the program's source comes from.
+#[derive(Debug, Clone, PartialEq)]
+enum Input {
+    File(String),
+    Stdin,
+    Inline(String),
+}
 
-    let mut parser = parser::Parser::new(tokens);
-    let tree = parser.parse();
+impl Input {
+    /// The name diagnostics refer to the source by, and the source itself.
+    fn read(&self) -> std::io::Result<(String, String)> {
+        match self {
+            Input::File(path) => Ok((path.clone(), std::fs::read_to_string(path)?)),
+            Input::Stdin => {
+                let mut source = String::new();
+                std::io::stdin().read_to_string(&mut source)?;
+                Ok(("<stdin>".to_string(), source))
+            }
+            Input::Inline(source) => Ok(("<-e>".to_string(), source.clone())),
+        }
+    }
+}
+
+struct Options {
+    command: Option<Command>,
+    input: Option<Input>,
+    backend: Backend,
+    layout: Option<Layout>,
+    max_steps: Option<u64>,
+    max_depth: Option<usize>,
+    timeout: Option<Duration>,
+    help: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            command: None,
+            input: None,
+            backend: Backend::Tree(GcConfig::default()),
+            layout: None,
+            max_steps: None,
+            max_depth: None,
+            timeout: None,
+            help: false,
+        };
+        let mut gc_stress = false;
+        let mut operands_only = false;
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+            match arg.as_str() {
+                path if operands_only => options.set_input(Input::File(path.to_string()))?,
+                "--" => operands_only = true,
+                // only before the source, so it can't be mistaken for one
+                "-h" | "--help" if options.input.is_none() => {
+                    options.help = true;
+                    return Ok(options);
+                }
+                "--backend" => {
+                    options.backend = match value(&arg)?.as_str() {
+                        "tree" => Backend::Tree(GcConfig::default()),
+                        "vm" => Backend::Vm(GcConfig::default()),
+                        other => return Err(format!("Unknown backend {:?}, expected 'tree' or 'vm'", other)),
+                    }
+                }
+                "--format" => {
+                    options.layout = Some(match value(&arg)?.as_str() {
+                        "text" => Layout::Text,
+                        "json" => Layout::Dump(dump::Format::Json),
+                        "sexpr" => Layout::Dump(dump::Format::Sexpr),
+                        other => return Err(format!("Unknown format {:?}, expected 'text', 'json' or 'sexpr'", other)),
+                    })
+                }
+                "--gc-stress" => gc_stress = true,
+                "--max-steps" => options.max_steps = Some(number(&arg, &value(&arg)?)?),
+                "--max-depth" => options.max_depth = Some(number(&arg, &value(&arg)?)?),
+                "--timeout" => options.timeout = Some(Duration::from_millis(number(&arg, &value(&arg)?)?)),
+                "-e" => options.set_input(Input::Inline(value(&arg)?))?,
+                "-" => options.set_input(Input::Stdin)?,
+                flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+                name if options.command.is_none() && options.input.is_none() && Command::parse(name).is_some() => {
+                    options.command = Command::parse(name);
+                }
+                path => options.set_input(Input::File(path.to_string()))?,
+            }
+        }
 
-    //println!("{:?}", tree);
+        // `--gc-stress` collects on every allocation, to catch objects
+        // that aren't rooted
+        if gc_stress {
+            match &mut options.backend {
+                Backend::Tree(gc) | Backend::Vm(gc) => gc.stress = true,
+            }
+        }
+        Ok(options)
+    }
+
+    fn set_input(&mut self, input: Input) -> Result<(), String> {
+        if self.input.is_some() {
+            return Err("Only one source can be given".to_string());
+        }
+        self.input = Some(input);
+        Ok(())
+    }
 
-    // print the result
-    interpret(tree)
+    /// The limits asked for, on top of `defaults`.
+    fn limits(&self, defaults: Limits) -> Limits {
+        Limits {
+            max_steps: self.max_steps.or(defaults.max_steps),
+            max_call_depth: self.max_depth.or(defaults.max_call_depth),
+            timeout: self.timeout.or(defaults.timeout),
+        }
+    }
 }
 
-// helper method for debugging AST
-fn print_ast(node: &Expression, indent: usize) {
-    // print the node
-    for _ in 0..indent {
-        print!("  ");
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} expects a number, got {:?}", flag, value))
+}
+
+/// Runs the tree on the tree-walker, stopping at the first runtime error.
+fn interpret(source_name: &str, source: &str, statements: Vec<Box<Statement>>, gc: GcConfig, limits: Limits) -> i32 {
+    let mut interpreter = Interpreter::with_gc(gc);
+    interpreter.set_limits(limits);
+    match interpreter.execute(statements) {
+        Ok(_) => 0,
+        Err(e) => {
+            interpreter.report(source_name, source, &Diagnostic::from(&e));
+            EX_SOFTWARE
+        }
     }
-    println!("{:?}", node);
-    // print the children
-    for child in node.children() {
-        print_ast(child, indent + 2);
+}
+
+fn compile(source_name: &str, source: &str, vm: &mut VM, statements: &Vec<Box<Statement>>) -> Option<ObjRef> {
+    match vm::compiler::Compiler::new(vm.heap()).compile(statements) {
+        Ok(script) => Some(script),
+        Err(errors) => {
+            for e in &errors {
+                report(source_name, source, Diagnostic::from(e));
+            }
+            None
+        }
     }
 }
 
+fn interpret_bytecode(source_name: &str, source: &str, statements: Vec<Box<Statement>>, gc: GcConfig, limits: Limits) -> i32 {
+    let mut vm = VM::with_gc(gc);
+    vm.set_limits(limits);
+    let script = match compile(source_name, source, &mut vm, &statements) {
+        Some(script) => script,
+        None => return EX_DATAERR,
+    };
+
+    match vm.interpret(script) {
+        Ok(()) => 0,
+        Err(e) => {
+            report(source_name, source, Diagnostic::from(&e));
+            EX_SOFTWARE
+        }
+    }
+}
 
-fn run_file(filename: String) {
-    // read the file
-    let contents = std::fs::read_to_string(filename).expect("Something went wrong reading the file");
-    // run the file
-    run(contents);
+/// Scans, parses and resolves `source`, reporting any errors. Returns the
+/// tree only if it is ready to run.
+fn parse(source_name: &str, source: &str) -> Option<Vec<Box<Statement>>> {
+    report_errors(source_name, source, lox_rust::parse(source))
 }
 
-fn run_prompt() {
-    // loop until user types exit
-    loop {
-        // get input from user
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).expect("Failed to read line");
-        // run the input
-        //print!("> {}", input);
-        run(input);
+fn report_errors<T>(source_name: &str, source: &str, result: Result<T, Vec<Diagnostic>>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                report(source_name, source, diagnostic);
+            }
+            None
+        }
     }
 }
 
-fn main() {
-    // take a file to read first, first arg
-    let filename = std::env::args().nth(1);
+fn run(source_name: &str, source: &str, options: &Options) -> i32 {
+    let tree = match parse(source_name, source) {
+        Some(tree) => tree,
+        None => return EX_DATAERR,
+    };
+
+    match options.backend {
+        Backend::Tree(gc) => interpret(source_name, source, tree, gc, options.limits(Limits::default())),
+        Backend::Vm(gc) => interpret_bytecode(source_name, source, tree, gc, options.limits(Limits::default())),
+    }
+}
 
-    // if there is a file, run it
-    if filename.is_some() {
-        run_file(filename.unwrap());
+/// Reports every error a program has without running it. The VM's compiler
+/// has a few limits of its own, so those are checked too when it is chosen.
+fn check(source_name: &str, source: &str, options: &Options) -> i32 {
+    let tree = match parse(source_name, source) {
+        Some(tree) => tree,
+        None => return EX_DATAERR,
+    };
+    if let Backend::Vm(gc) = options.backend {
+        if compile(source_name, source, &mut VM::with_gc(gc), &tree).is_none() {
+            return EX_DATAERR;
+        }
+    }
+    0
+}
+
+fn print_tokens(source_name: &str, source: &str, layout: Layout) -> i32 {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().clone();
+    match layout {
+        Layout::Text => {
+            for token in tokens {
+                println!("{:?} {:?} at {}", token.token_type, token.lexeme, token.span);
+            }
+        }
+        Layout::Dump(format) => print!("{}", dump::tokens(&tokens, format)),
+    }
+    if scanner.errors().is_empty() {
+        return 0;
+    }
+    for e in scanner.errors() {
+        report(source_name, source, Diagnostic::from(e));
+    }
+    EX_DATAERR
+}
+
+fn print_ast(source_name: &str, source: &str, layout: Layout) -> i32 {
+    match report_errors(source_name, source, lox_rust::parse_syntax(source)) {
+        Some(tree) => {
+            match layout {
+                Layout::Text => {
+                    for statement in tree {
+                        println!("{:#?}", statement);
+                    }
+                }
+                Layout::Dump(format) => print!("{}", dump::ast(&tree, format)),
+            }
+            0
+        }
+        None => EX_DATAERR,
+    }
+}
+
+fn format_source(source_name: &str, source: &str) -> i32 {
+    match report_errors(source_name, source, lox_rust::parse_syntax(source)) {
+        Some(_) => {
+            print!("{}", formatter::format(source));
+            0
+        }
+        None => EX_DATAERR,
+    }
+}
+
+/// Prints the bytecode a program compiles to instead of running it.
+fn disassemble(source_name: &str, source: &str) -> i32 {
+    let tree = match parse(source_name, source) {
+        Some(tree) => tree,
+        None => return EX_DATAERR,
+    };
+    let mut vm = VM::new();
+    match compile(source_name, source, &mut vm, &tree) {
+        Some(script) => {
+            let heap = vm.heap();
+            let function = heap.function(script);
+            print!("{}", vm::disassembler::disassemble(heap, &function.chunk, &function.to_string()));
+            0
+        }
+        None => EX_DATAERR,
+    }
+}
+
+fn run_prompt(options: &Options) -> i32 {
+    // the VM has no way to hand back the value of an expression to echo
+    let gc = match options.backend {
+        Backend::Tree(gc) => gc,
+        Backend::Vm(_) => {
+            eprintln!("The REPL only runs on the tree backend");
+            return EX_USAGE;
+        }
+    };
+    let mut repl = Repl::new();
+    *repl.interpreter() = Interpreter::with_gc(gc);
+    repl.interpreter().set_limits(options.limits(Limits::default()));
+    let result = if std::io::stdin().is_terminal() {
+        run_editor(&mut repl)
     } else {
-        // if there is no file, run the prompt
-        run_prompt();
+        let mut reader = PlainReader::new(std::io::stdin().lock(), repl.interpreter().output());
+        repl.run(&mut reader)
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to read input: {}", e);
+            EX_IOERR
+        }
+    }
+}
+
+/// Runs the REPL with line editing, keeping history between sessions.
+fn run_editor(repl: &mut Repl) -> std::io::Result<()> {
+    let mut editor = rustyline::DefaultEditor::new().map_err(std::io::Error::other)?;
+    let history = repl::history_path();
+    if let Some(path) = &history {
+        // there is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+    let result = repl.run(&mut editor);
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Could not save history to {}: {}", path.display(), e);
+        }
+    }
+    result
+}
+
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(EX_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
     }
+
+    let command = match (options.command, &options.input) {
+        (Some(command), _) => command,
+        (None, Some(_)) => Command::Run,
+        (None, None) => Command::Repl,
+    };
+    if command == Command::Repl {
+        if options.input.is_some() {
+            eprintln!("repl doesn't take a source\n\n{}", USAGE);
+            std::process::exit(EX_USAGE);
+        }
+        std::process::exit(run_prompt(&options));
+    }
+
+    let input = match &options.input {
+        Some(input) => input,
+        None => {
+            eprintln!("{:?} needs a file, - or -e <code>\n\n{}", command, USAGE);
+            std::process::exit(EX_USAGE);
+        }
+    };
+    let (source_name, source) = match input.read() {
+        Ok(read) => read,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", input_name(input), e);
+            std::process::exit(EX_IOERR);
+        }
+    };
+
+    let code = match command {
+        Command::Run => run(&source_name, &source, &options),
+        Command::Check => check(&source_name, &source, &options),
+        Command::Tokens => print_tokens(&source_name, &source, options.layout.unwrap_or(Layout::Text)),
+        Command::Ast => print_ast(&source_name, &source, options.layout.unwrap_or(Layout::Dump(dump::Format::Sexpr))),
+        Command::Fmt => format_source(&source_name, &source),
+        Command::Disassemble => disassemble(&source_name, &source),
+        Command::Repl => unreachable!("handled above"),
+    };
+    std::process::exit(code);
 }
 
+fn input_name(input: &Input) -> &str {
+    match input {
+        Input::File(path) => path,
+        Input::Stdin => "stdin",
+        Input::Inline(_) => "-e",
+    }
+}
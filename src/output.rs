@@ -0,0 +1,87 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+/// Where text written by a program or its interpreter goes. Clones share
+/// the same destination, so redirecting a sink also redirects every
+/// environment and closure already holding it.
+#[derive(Clone)]
+pub struct Sink(Rc<RefCell<Box<dyn Write>>>);
+
+impl Sink {
+    pub fn new(writer: impl Write + 'static) -> Sink {
+        Sink(Rc::new(RefCell::new(Box::new(writer))))
+    }
+
+    pub fn stdout() -> Sink {
+        Sink::new(io::stdout())
+    }
+
+    pub fn stderr() -> Sink {
+        Sink::new(io::stderr())
+    }
+
+    /// Sends everything written from now on to `writer` instead.
+    pub fn redirect(&self, writer: impl Write + 'static) {
+        *self.0.borrow_mut() = Box::new(writer);
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// An in-memory writer whose clones share one buffer, for capturing what
+/// is written to a sink.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_a_redirect() {
+        let first = SharedBuffer::new();
+        let second = SharedBuffer::new();
+        let sink = Sink::new(first.clone());
+        let mut clone = sink.clone();
+
+        write!(clone, "a").unwrap();
+        sink.redirect(second.clone());
+        write!(clone, "b").unwrap();
+
+        assert_eq!(first.contents(), "a");
+        assert_eq!(second.contents(), "b");
+    }
+}
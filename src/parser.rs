@@ -1,4 +1,4 @@
-use std::fmt::{self, Display};
+use std::{fmt::{self, Display}, rc::Rc};
 
 use crate::{
     expressions::{
@@ -6,29 +6,38 @@ use crate::{
         binary_expression::BinaryExpression,
         call_expression::CallExpression,
         expressions::Expression,
+        get_expression::GetExpression,
         grouping_expression::GroupingExpression,
         literal_expression::LiteralExpression,
         logical_expression::{LogicalExpression, LogicalExpressionOperator},
+        set_expression::SetExpression,
+        super_expression::SuperExpression,
+        this_expression::ThisExpression,
         unary_expression::UnaryExpression,
         var_expression::VarExpression,
     },
     statement::{
-        BlockStatement, BreakStatement, ExpressionStatement, FunctionStatement,
-        IfStatement, PrintStatement, Statement, VarStatement, WhileStatement,
+        BlockStatement, BreakStatement, ClassStatement, ExpressionStatement, FunctionStatement,
+        IfStatement, PrintStatement, ReturnStatement, Statement, VarStatement, WhileStatement,
     },
-    tokens::{Token, TokenType},
+    interner::Symbol,
+    tokens::{Span, Token, TokenType},
 };
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Number(f64),
-    String(String),
+    // shared, so copying a string never copies its text. Strings aren't
+    // interned: those built at runtime would never be freed.
+    String(Rc<str>),
     Boolean(bool),
+    Nil,
 }
 
 impl Literal {
     pub fn is_truthy(&self) -> bool {
         match self {
+            Literal::Nil => false,
             Literal::Boolean(b) => *b,
             Literal::Number(n) => *n != 0.0,
             Literal::String(s) => !s.is_empty(),
@@ -36,22 +45,28 @@ impl Literal {
     }
 }
 
-#[derive(Debug, Clone)]
-struct ParseError {
-    message: String,
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub token: Token,
+    pub message: String,
 }
 
 impl ParseError {
     fn new(token: Token, message: &str) -> ParseError {
         ParseError {
-            message: format!("{} at '{}' on line {}", message, token.lexeme, token.line),
+            token,
+            message: message.to_string(),
         }
     }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+        if self.token.token_type == TokenType::EOF {
+            write!(f, "{} at end on line {}", self.message, self.token.line)
+        } else {
+            write!(f, "{} at '{}' on line {}", self.message, self.token.lexeme, self.token.line)
+        }
     }
 }
 
@@ -63,6 +78,7 @@ impl fmt::Display for Literal {
             Literal::Number(n) => write!(f, "{}", n),
             Literal::String(s) => write!(f, "{}", s),
             Literal::Boolean(b) => write!(f, "{}", b),
+            Literal::Nil => write!(f, "nil"),
         }
     }
 }
@@ -70,6 +86,9 @@ impl fmt::Display for Literal {
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    errors: Vec<ParseError>,
+    // number of blocks currently open, so recovery can stop at their closing brace
+    block_depth: usize,
 }
 
 impl Parser {
@@ -77,24 +96,45 @@ impl Parser {
         Parser {
             tokens: tokens.clone(),
             pos: 0,
+            errors: vec![],
+            block_depth: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Box<Statement>> {
+    /// Parses the whole token stream. On a syntax error the parser skips to
+    /// the next statement and carries on, so every error in the file is
+    /// reported at once.
+    pub fn parse(&mut self) -> std::result::Result<Vec<Box<Statement>>, Vec<ParseError>> {
         let mut statements: Vec<Box<Statement>> = vec![];
         while !self.is_at_end() {
-            let result = self.declaration();
-            if result.is_ok() {
-                statements.push(result.unwrap());
-            } else {
-                println!("Statements so far: {:?}", statements);
-                panic!("Error: {}", result.err().unwrap());
+            if let Some(statement) = self.recovering_declaration() {
+                statements.push(statement);
+            }
+        }
+
+        if self.errors.is_empty() {
+            return Ok(statements);
+        }
+        return Err(std::mem::take(&mut self.errors));
+    }
+
+    // records the error and synchronizes instead of unwinding any further
+    fn recovering_declaration(&mut self) -> Option<Box<Statement>> {
+        match self.declaration() {
+            Ok(statement) => Some(statement),
+            Err(error) => {
+                self.errors.push(*error);
+                self.synchronize();
+                None
             }
         }
-        return statements;
     }
 
     fn declaration(&mut self) -> Result<Statement> {
+        if self.match_tokens(vec![TokenType::Class]) {
+            return self.class_declaration();
+        }
+
         if self.match_tokens(vec![TokenType::Var]) {
             return self.var_declaration();
         }
@@ -107,56 +147,101 @@ impl Parser {
     }
 
     fn var_declaration(&mut self) -> Result<Statement> {
+        let start = self.previous().span;
         let identifier = self.consume(
             TokenType::Idenfitier("".to_string()),
             "Expect variable name.",
-        );
-        let identifier_lexeme = identifier.lexeme.clone();
-        self.consume(TokenType::Equal, "Expect '=' after variable name.");
+        )?;
+        let identifier_lexeme = Symbol::intern(&identifier.lexeme);
 
-        let initializer = self.expression()?;
+        let initializer = if self.match_tokens(vec![TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
 
         self.consume(
             TokenType::Semicolon,
             "Expect ';' after variable declaration.",
-        );
-        return Ok(Box::new(Statement::Var(VarStatement::new(identifier_lexeme, initializer))));
+        )?;
+        return Ok(Box::new(Statement::Var(VarStatement::new(identifier_lexeme, initializer, self.span_from(start)))));
+    }
+
+    fn class_declaration(&mut self) -> Result<Statement> {
+        let start = self.previous().span;
+        let identifier = self.consume(
+            TokenType::Idenfitier("".to_string()),
+            "Expect class name.",
+        )?;
+        let identifier_lexeme = Symbol::intern(&identifier.lexeme);
+
+        let superclass = if self.match_tokens(vec![TokenType::Less]) {
+            let name = self.consume(
+                TokenType::Idenfitier("".to_string()),
+                "Expect superclass name.",
+            )?.clone();
+            Some(Box::new(Expression::Var(VarExpression::new(Symbol::intern(&name.lexeme), name.span))))
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods: Vec<FunctionStatement> = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let start = self.peek().span;
+            methods.push(*self.function("method", start)?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        return Ok(Box::new(Statement::Class(ClassStatement::new(identifier_lexeme, superclass, methods, self.span_from(start)))));
     }
 
     fn fun_declaration(&mut self) -> Result<Statement> {
+        let start = self.previous().span;
+        let function = self.function("function", start)?;
+        return Ok(Box::new(Statement::Function(*function)));
+    }
+
+    // parses the name, parameters and body shared by functions and methods,
+    // `start` being the span of the `fun` keyword or method name
+    fn function(&mut self, kind: &str, start: Span) -> Result<FunctionStatement> {
         let identifier = self.consume(
             TokenType::Idenfitier("".to_string()),
-            "Expect function name.",
-        );
-        let identifier_lexeme = identifier.lexeme.clone();
-        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
-        let mut parameters: Vec<String> = vec![];
+            &format!("Expect {} name.", kind),
+        )?;
+        let identifier_lexeme = Symbol::intern(&identifier.lexeme);
+        self.consume(TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let mut parameters: Vec<Symbol> = vec![];
         if !self.check(TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    panic!("Cannot have more than 255 parameters.");
+                    return Err(Box::new(ParseError::new(
+                        self.peek().clone(),
+                        "Can't have more than 255 parameters.",
+                    )));
                 }
-                parameters.push(
-                    self.consume(
+                parameters.push(Symbol::intern(
+                    &self.consume(
                         TokenType::Idenfitier("".to_string()),
                         "Expect parameter name.",
-                    )
-                    .lexeme
-                    .clone(),
-                );
+                    )?
+                    .lexeme,
+                ));
                 if !self.match_tokens(vec![TokenType::Comma]) {
                     break;
                 }
             }
         }
-        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
-        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
         let body = self.block_statement()?;
-        return Ok(Box::new(Statement::Function(FunctionStatement::new(
+        return Ok(Box::new(FunctionStatement::new(
             identifier_lexeme,
             parameters,
             body,
-        ))));
+            self.span_from(start),
+        )));
     }
 
     fn statement(&mut self) -> Result<Statement> {
@@ -177,6 +262,10 @@ impl Parser {
             return self.break_statement();
         }
 
+        if self.match_tokens(vec![TokenType::Return]) {
+            return self.return_statement();
+        }
+
         if self.match_tokens(vec![TokenType::LeftBrace]) {
             return self.block_statement();
         }
@@ -185,9 +274,10 @@ impl Parser {
     }
 
     fn if_statement(&mut self) -> Result<Statement> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after if condition.");
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
 
         let then_branch = self.statement()?;
         let else_branch = if self.match_tokens(vec![TokenType::Else]) {
@@ -200,45 +290,61 @@ impl Parser {
             condition,
             then_branch,
             else_branch,
+            self.span_from(start),
         ))));
     }
 
     fn break_statement(&mut self) -> Result<Statement> {
-        self.consume(TokenType::Semicolon, "Expect ';' after break.");
-        return Ok(Box::new(Statement::Break(BreakStatement::new())));
+        let start = self.previous().span;
+        self.consume(TokenType::Semicolon, "Expect ';' after break.")?;
+        return Ok(Box::new(Statement::Break(BreakStatement::new(self.span_from(start)))));
+    }
+
+    fn return_statement(&mut self) -> Result<Statement> {
+        let start = self.previous().span;
+        let value = if !self.check(TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        return Ok(Box::new(Statement::Return(ReturnStatement::new(value, self.span_from(start)))));
     }
 
     fn print_statement(&mut self) -> Result<Statement> {
+        let start = self.previous().span;
         let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.");
-        return Ok(Box::new(Statement::Print(PrintStatement::new(value))));
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        return Ok(Box::new(Statement::Print(PrintStatement::new(value, self.span_from(start)))));
     }
 
     fn block_statement(&mut self) -> Result<Statement> {
+        let start = self.previous().span;
         let mut statements: Vec<Box<Statement>> = vec![];
+        self.block_depth += 1;
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            let result = self.declaration();
-            if result.is_ok() {
-                statements.push(result.unwrap());
-            } else {
-                panic!("Error: {}", result.err().unwrap());
+            if let Some(statement) = self.recovering_declaration() {
+                statements.push(statement);
             }
         }
+        self.block_depth -= 1;
 
-        self.consume(TokenType::RightBrace, "Expect '}' after block.");
-        return Ok(Box::new(Statement::Block(BlockStatement::new(statements))));
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        return Ok(Box::new(Statement::Block(BlockStatement::new(statements, self.span_from(start)))));
     }
 
     fn while_statement(&mut self) -> Result<Statement> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
         let body = self.statement()?;
-        return Ok(Box::new(Statement::While(WhileStatement::new(condition, body))));
+        return Ok(Box::new(Statement::While(WhileStatement::new(condition, body, self.span_from(start)))));
     }
 
     fn for_statement(&mut self) -> Result<Statement> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
         let initializer = if self.match_tokens(vec![TokenType::Semicolon]) {
             None
         } else if self.match_tokens(vec![TokenType::Var]) {
@@ -252,38 +358,42 @@ impl Parser {
         } else {
             None
         };
-        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.");
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
 
         let increment = if !self.check(TokenType::RightParen) {
             Some(self.expression()?)
         } else {
             None
         };
-        self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
         let mut body = self.statement()?;
+        // the desugared statements all point back at the whole loop
+        let span = self.span_from(start);
 
         // if there is a increment, do it after the main body
         if let Some(increment) = increment {
+            let increment_span = increment.span();
             body = Box::new(Statement::Block(BlockStatement::new(vec![
                 body,
-                Box::new(Statement::Expression(ExpressionStatement::new(increment))),
-            ])));
+                Box::new(Statement::Expression(ExpressionStatement::new(increment, increment_span))),
+            ], span)));
         };
 
         // if there is a condition, we wrap the statement in a loop
         if let Some(condition) = condition {
-            body = Box::new(Statement::While(WhileStatement::new(condition, body)));
+            body = Box::new(Statement::While(WhileStatement::new(condition, body, span)));
         } else {
             body = Box::new(Statement::While(WhileStatement::new(
-                Box::new(Expression::Literal(LiteralExpression::new(Literal::Boolean(true)))),
+                Box::new(Expression::Literal(LiteralExpression::new(Literal::Boolean(true), start))),
                 body,
+                span,
             )));
         };
 
         // if there is an initializer, we wrap the statement in a block
         if let Some(initializer) = initializer {
-            body = Box::new(Statement::Block(BlockStatement::new(vec![initializer, body])));
+            body = Box::new(Statement::Block(BlockStatement::new(vec![initializer, body], span)));
         };
 
         return Ok(body);
@@ -291,8 +401,9 @@ impl Parser {
 
     fn expression_statement(&mut self) -> Result<Statement> {
         let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
-        return Ok(Box::new(Statement::Expression(ExpressionStatement::new(expr))));
+        let start = expr.span();
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        return Ok(Box::new(Statement::Expression(ExpressionStatement::new(expr, self.span_from(start)))));
     }
 
     fn expression(&mut self) -> Result<Expression> {
@@ -305,11 +416,17 @@ impl Parser {
         if self.match_tokens(vec![TokenType::Equal]) {
             let equals = self.previous().clone();
             let value = self.assignment()?;
+            let span = expr.span().to(value.span());
 
             // check if expr is a VarExpression
             if let Expression::Var(var_expr) = *expr {
-                let name = var_expr.name.clone();
-                return Ok(Box::new(Expression::Assignment(AssignmentExpression::new(name, value))));
+                let name = var_expr.name;
+                return Ok(Box::new(Expression::Assignment(AssignmentExpression::new(name, value, span))));
+            }
+
+            // a property access on the left becomes a field assignment
+            if let Expression::Get(get_expr) = *expr {
+                return Ok(Box::new(Expression::Set(SetExpression::new(get_expr.object, get_expr.name, value, span))));
             }
 
             return Err(Box::new(ParseError::new(
@@ -326,11 +443,14 @@ impl Parser {
 
         while self.match_tokens(vec![TokenType::Or]) {
             let op = self.previous().clone();
-            let right = self.and();
+            let right = self.and()?;
+            let left = expr?;
+            let span = left.span().to(right.span());
             expr = Ok(Box::new(Expression::Logical(LogicalExpression::new(
-                expr?,
-                right?,
+                left,
+                right,
                 LogicalExpressionOperator::Or,
+                span,
             ))));
         }
 
@@ -342,11 +462,14 @@ impl Parser {
 
         while self.match_tokens(vec![TokenType::And]) {
             let op = self.previous().clone();
-            let right = self.equality();
+            let right = self.equality()?;
+            let left = expr?;
+            let span = left.span().to(right.span());
             expr = Ok(Box::new(Expression::Logical(LogicalExpression::new(
-                expr?,
-                right?,
+                left,
+                right,
                 LogicalExpressionOperator::And,
+                span,
             ))));
         }
 
@@ -358,8 +481,10 @@ impl Parser {
 
         while self.match_tokens(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
             let op = self.previous().clone();
-            let right = self.comparison();
-            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, expr?, right?))));
+            let right = self.comparison()?;
+            let left = expr?;
+            let span = left.span().to(right.span());
+            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, left, right, span))));
         }
 
         return expr;
@@ -375,8 +500,10 @@ impl Parser {
             TokenType::LessEqual,
         ]) {
             let op = self.previous().clone();
-            let right = self.term();
-            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, expr?, right?))));
+            let right = self.term()?;
+            let left = expr?;
+            let span = left.span().to(right.span());
+            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, left, right, span))));
         }
 
         return expr;
@@ -387,8 +514,10 @@ impl Parser {
 
         while self.match_tokens(vec![TokenType::Minus, TokenType::Plus]) {
             let op = self.previous().clone();
-            let right = self.factor();
-            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, expr?, right?))));
+            let right = self.factor()?;
+            let left = expr?;
+            let span = left.span().to(right.span());
+            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, left, right, span))));
         }
 
         return expr;
@@ -399,8 +528,10 @@ impl Parser {
 
         while self.match_tokens(vec![TokenType::Slash, TokenType::Star]) {
             let op = self.previous().clone();
-            let right = self.unary();
-            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, expr?, right?))));
+            let right = self.unary()?;
+            let left = expr?;
+            let span = left.span().to(right.span());
+            expr = Ok(Box::new(Expression::Binary(BinaryExpression::new(op, left, right, span))));
         }
 
         return expr;
@@ -409,8 +540,9 @@ impl Parser {
     fn unary(&mut self) -> Result<Expression> {
         if self.match_tokens(vec![TokenType::Bang, TokenType::Minus]) {
             let op = self.previous().clone();
-            let right = self.unary();
-            return Ok(Box::new(Expression::Unary(UnaryExpression::new(op, right?))));
+            let right = self.unary()?;
+            let span = op.span.to(right.span());
+            return Ok(Box::new(Expression::Unary(UnaryExpression::new(op, right, span))));
         }
 
         return self.call();
@@ -422,6 +554,13 @@ impl Parser {
         loop {
             if self.match_tokens(vec![TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_tokens(vec![TokenType::Dot]) {
+                let name = self.consume(
+                    TokenType::Idenfitier("".to_string()),
+                    "Expect property name after '.'.",
+                )?.clone();
+                let span = expr.span().to(name.span);
+                expr = Box::new(Expression::Get(GetExpression::new(expr, Symbol::intern(&name.lexeme), span)));
             } else {
                 break;
             }
@@ -450,45 +589,65 @@ impl Parser {
             }
         };
 
-        let paren = self.consume(TokenType::RightParen, "Expect ) after arguments.");
+        let paren = self.consume(TokenType::RightParen, "Expect ) after arguments.")?.clone();
 
-        let c = CallExpression::new(callee, (*paren).clone(), arguments);
+        let span = callee.span().to(paren.span);
+        let c = CallExpression::new(callee, paren, arguments, span);
         return Ok(Box::new(Expression::Call(c)));
     }
 
     fn primary(&mut self) -> Result<Expression> {
         let token_type = self.tokens[self.pos].token_type.clone();
+        let start = self.peek().span;
         match token_type {
             TokenType::False => {
                 self.advance();
                 return Ok(Box::new(Expression::Literal(LiteralExpression::new(
                     Literal::Boolean(false),
+                    start,
                 ))));
             }
             TokenType::True => {
                 self.advance();
-                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::Boolean(true)))));
+                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::Boolean(true), start))));
+            }
+            TokenType::Nil => {
+                self.advance();
+                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::Nil, start))));
             }
             TokenType::Number(val) => {
                 let v = val.clone();
                 self.advance();
-                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::Number(v)))));
+                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::Number(v), start))));
             }
             TokenType::String(val) => {
-                let v = val.clone();
+                let v: Rc<str> = Rc::from(val.as_str());
                 self.advance();
-                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::String(v)))));
+                return Ok(Box::new(Expression::Literal(LiteralExpression::new(Literal::String(v), start))));
             }
             TokenType::LeftParen => {
                 self.advance();
-                let expr = self.expression();
-                self.consume(TokenType::RightParen, "Expect ')' after expression.");
-                return Ok(Box::new(Expression::Grouping(GroupingExpression::new(expr?))));
+                let expr = self.expression()?;
+                self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+                return Ok(Box::new(Expression::Grouping(GroupingExpression::new(expr, self.span_from(start)))));
 
             }
+            TokenType::Super => {
+                self.advance();
+                self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+                let method = Symbol::intern(&self.consume(
+                    TokenType::Idenfitier("".to_string()),
+                    "Expect superclass method name.",
+                )?.lexeme);
+                return Ok(Box::new(Expression::Super(SuperExpression::new(method, self.span_from(start)))));
+            }
+            TokenType::This => {
+                self.advance();
+                return Ok(Box::new(Expression::This(ThisExpression::new(start))));
+            }
             TokenType::Idenfitier(val) => {
                 self.advance();
-                return Ok(Box::new(Expression::Var(VarExpression::new(val.clone()))));
+                return Ok(Box::new(Expression::Var(VarExpression::new(Symbol::intern(&val), start))));
             }
             _ => {
                 return Err(Box::new(ParseError::new(
@@ -504,14 +663,49 @@ impl Parser {
         return self.peek().token_type == TokenType::EOF;
     }
 
-    fn consume(&mut self, token: TokenType, message: &str) -> &Token {
+    fn consume(&mut self, token: TokenType, message: &str) -> std::result::Result<&Token, Box<ParseError>> {
         if self.check(token) {
-            return self.advance();
+            return Ok(self.advance());
         } else {
-            panic!("{}", message);
+            return Err(Box::new(ParseError::new(self.peek().clone(), message)));
         }
     }
 
+    // discards tokens until the start of the next statement, so one error
+    // doesn't cascade into a pile of spurious ones
+    fn synchronize(&mut self) {
+        if self.closes_block() {
+            return;
+        }
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon || self.closes_block() {
+                return;
+            }
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    // the span from `start` up to and including the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
+    }
+
+    fn closes_block(&self) -> bool {
+        self.block_depth > 0 && self.check(TokenType::RightBrace)
+    }
+
     fn check(&self, token: TokenType) -> bool {
         if self.is_at_end() {
             return false;
@@ -544,3 +738,90 @@ impl Parser {
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Scanner;
+
+    use super::*;
+
+    fn parse(source: &str) -> std::result::Result<Vec<Box<Statement>>, Vec<ParseError>> {
+        let mut scanner = Scanner::new(source.to_string());
+        Parser::new(scanner.scan_tokens()).parse()
+    }
+
+    #[test]
+    fn it_parses_valid_programs() {
+        let statements = parse("var a = 1; print a;").unwrap();
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn it_reports_the_token_and_line_of_an_error() {
+        let errors = parse("var a = 1;\nvar = 2;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Expect variable name.");
+        assert_eq!(errors[0].token.token_type, TokenType::Equal);
+        assert_eq!(errors[0].token.line, 2);
+        assert_eq!(errors[0].to_string(), "Expect variable name. at '=' on line 2");
+    }
+
+    #[test]
+    fn it_collects_every_error_in_a_file() {
+        let errors = parse("
+            var x = ;
+            fun (a) {}
+            print 2;
+            print 1
+            var y = 3;
+            while (true print 3;
+        ").unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Expect expression.",
+                "Expect function name.",
+                "Expect ';' after value.",
+                "Expect ')' after condition.",
+            ]
+        );
+        let lines: Vec<usize> = errors.iter().map(|e| e.token.line).collect();
+        assert_eq!(lines, vec![2, 3, 6, 7]);
+    }
+
+    #[test]
+    fn it_reports_errors_inside_blocks() {
+        let errors = parse("fun f() { var a = ; print a } print 1;").unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["Expect expression.", "Expect ';' after value."]);
+    }
+
+    #[test]
+    fn it_reports_unexpected_end_of_input() {
+        let errors = parse("print 1").unwrap_err();
+        assert_eq!(errors[0].to_string(), "Expect ';' after value. at end on line 1");
+    }
+
+    #[test]
+    fn it_records_spans_on_nodes() {
+        let statements = parse("print 1;\nvar total = a.b(1, 2) + -c;").unwrap();
+        assert_eq!(statements[0].span(), Span::new(0, 8, 1, 1));
+        assert_eq!(statements[1].span(), Span::new(9, 36, 2, 1));
+        match statements[1].as_ref() {
+            Statement::Var(var) => assert_eq!(var.span.end, 36),
+            _ => panic!("expected a var statement"),
+        }
+    }
+
+    #[test]
+    fn it_spans_expressions_from_their_first_to_last_token() {
+        let mut scanner = Scanner::new("  a.b(1, 2) + -c".to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let expr = parser.expression().unwrap();
+        assert_eq!(expr.span(), Span::new(2, 16, 1, 3));
+        let children = expr.children();
+        assert_eq!(children[0].span(), Span::new(2, 11, 1, 3));
+        assert_eq!(children[1].span(), Span::new(14, 16, 1, 15));
+    }
+}
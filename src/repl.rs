@@ -0,0 +1,414 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use rustyline::{error::ReadlineError, history::History};
+
+use crate::{
+    diagnostics::{UNTERMINATED_COMMENT, UNTERMINATED_STRING},
+    expressions::expressions::{Expression, ExpressionResult},
+    interpreter::Interpreter,
+    output::Sink,
+    parser::Literal,
+    scanner::Scanner,
+    statement::Statement,
+    tokens::TokenType,
+};
+
+const PROMPT: &str = "> ";
+// shown while a statement is still open from the lines before
+const CONTINUATION_PROMPT: &str = "... ";
+const SOURCE_NAME: &str = "<stdin>";
+const HISTORY_FILE: &str = ".lox_history";
+
+const HELP: &str = "\
+:env            list every global and its value
+:ast <expr>     show the syntax tree of an expression
+:tokens <src>   show the tokens the scanner produces
+:load <file>    run a file in this session
+:time <expr>    run code and show how long it took
+:reset          forget every global
+:help           show this list";
+
+/// Where the REPL gets its lines from.
+pub trait LineReader {
+    /// Shows `prompt` and reads one line, without its line ending. Returns
+    /// `None` at the end of input, and an `Interrupted` error when the user
+    /// abandons what they have typed.
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>>;
+
+    /// Records a complete entry, for readers that keep a history.
+    fn add_history(&mut self, _entry: &str) {}
+}
+
+/// Reads plain lines, e.g. piped into stdin, writing prompts to `output`.
+pub struct PlainReader<R> {
+    input: R,
+    output: Sink,
+}
+
+impl<R: BufRead> PlainReader<R> {
+    pub fn new(input: R, output: Sink) -> PlainReader<R> {
+        PlainReader { input, output }
+    }
+}
+
+impl<R: BufRead> LineReader for PlainReader<R> {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        write!(self.output, "{}", prompt)?;
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            // leave the shell's prompt on a line of its own
+            writeln!(self.output)?;
+            return Ok(None);
+        }
+        let length = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(length);
+        Ok(Some(line))
+    }
+}
+
+/// A terminal with line editing and history.
+impl LineReader for rustyline::DefaultEditor {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        match self.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(ReadlineError::Interrupted) => Err(io::ErrorKind::Interrupted.into()),
+            Err(ReadlineError::Io(e)) => Err(e),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        // a failure to remember an entry isn't worth interrupting the session
+        let _ = self.history_mut().add(entry);
+    }
+}
+
+/// The file REPL history is kept in between sessions, `~/.lox_history`.
+pub fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Whether `source` stops in the middle of something: an unclosed
+/// parenthesis or brace, or an unterminated string or block comment. The
+/// REPL keeps reading lines until it isn't.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source.to_string());
+    let mut depth: i32 = 0;
+    for token in scanner.scan_tokens() {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    // more closers than openers is an error for the parser to report
+    depth > 0 || scanner.errors().iter().any(|e| e.code == UNTERMINATED_STRING || e.code == UNTERMINATED_COMMENT)
+}
+
+// a bare expression needs its semicolon to parse as a statement
+fn terminated(source: &str) -> String {
+    if source.trim_end().ends_with(';') || source.trim_end().ends_with('}') {
+        source.to_string()
+    } else {
+        format!("{};", source)
+    }
+}
+
+// lists each node with its children indented below it
+fn format_ast(node: &Expression, indent: usize, out: &mut String) {
+    out.push_str(&"  ".repeat(indent));
+    out.push_str(&format!("{:?}\n", node));
+    for child in node.children() {
+        format_ast(child, indent + 2, out);
+    }
+}
+
+/// An interactive session on the tree-walker. One interpreter lives for the
+/// whole session, so every line sees what earlier lines defined, even if
+/// they failed part way through.
+pub struct Repl {
+    interpreter: Interpreter,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl { interpreter: Interpreter::new() }
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Runs one line. Errors are reported to the interpreter's diagnostics
+    /// sink, and the value of a trailing bare expression is printed unless
+    /// it is nil, so calls made for their effect don't echo anything.
+    pub fn eval_line(&mut self, line: &str) {
+        if let Some(value) = self.eval(SOURCE_NAME, line) {
+            self.echo(value);
+        }
+    }
+
+    // runs `source`, reporting any errors against `source_name`
+    fn eval(&mut self, source_name: &str, source: &str) -> Option<ExpressionResult> {
+        match self.interpreter.eval(source) {
+            Ok(value) => Some(value),
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    self.interpreter.report(source_name, source, diagnostic);
+                }
+                None
+            }
+        }
+    }
+
+    fn echo(&mut self, value: ExpressionResult) {
+        if value != ExpressionResult::Literal(Literal::Nil) {
+            self.say(&value.to_string());
+        }
+    }
+
+    fn say(&mut self, text: &str) {
+        let _ = writeln!(self.interpreter.output(), "{}", text);
+    }
+
+    fn complain(&mut self, text: &str) {
+        let _ = writeln!(self.interpreter.diagnostics(), "{}", text);
+    }
+
+    /// Runs a `:` command, which inspects or changes the session instead of
+    /// running Lox. Output goes to the output sink, and mistakes to the
+    /// diagnostics sink.
+    pub fn command(&mut self, line: &str) {
+        let line = line.trim().trim_start_matches(':');
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        match (name, argument) {
+            ("env", _) => {
+                for (name, value) in self.interpreter.globals() {
+                    self.say(&format!("{} = {}", name, value));
+                }
+            }
+            ("ast", "") | ("tokens", "") | ("load", "") | ("time", "") => {
+                self.complain(&format!("Usage: :{} <{}>", name, if name == "load" { "file" } else { "code" }));
+            }
+            ("ast", source) => self.show_ast(source),
+            ("tokens", source) => {
+                let mut scanner = Scanner::new(source.to_string());
+                let tokens = scanner.scan_tokens().clone();
+                for token in tokens {
+                    self.say(&format!("{:?} {:?} at {}", token.token_type, token.lexeme, token.span));
+                }
+            }
+            ("load", path) => match std::fs::read_to_string(path) {
+                Ok(source) => {
+                    self.eval(path, &source);
+                }
+                Err(e) => self.complain(&format!("Could not read {}: {}", path, e)),
+            },
+            ("time", source) => {
+                let source = terminated(source);
+                let start = Instant::now();
+                let value = self.eval(SOURCE_NAME, &source);
+                let elapsed = start.elapsed();
+                // a run that failed says nothing about how long the code takes
+                let Some(value) = value else { return };
+                self.echo(value);
+                self.say(&format!("took {:.3}ms", elapsed.as_secs_f64() * 1000.0));
+            }
+            ("reset", _) => self.interpreter.reset(),
+            ("help", _) => self.say(HELP),
+            _ => self.complain(&format!("Unknown command ':{}', try :help", name)),
+        }
+    }
+
+    fn show_ast(&mut self, source: &str) {
+        let source = terminated(source);
+        let statements = match crate::parse(&source) {
+            Ok(statements) => statements,
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    self.interpreter.report(SOURCE_NAME, &source, diagnostic);
+                }
+                return;
+            }
+        };
+        for statement in statements {
+            match *statement {
+                Statement::Expression(statement) => {
+                    let mut out = String::new();
+                    format_ast(&statement.expression, 0, &mut out);
+                    self.say(out.trim_end());
+                }
+                statement => self.say(&format!("{:#?}", statement)),
+            }
+        }
+    }
+
+    /// Prompts for and runs entries from `reader` until it ends. An entry
+    /// continues over as many lines as it takes to complete it.
+    pub fn run(&mut self, reader: &mut impl LineReader) -> io::Result<()> {
+        let mut entry = String::new();
+        loop {
+            let prompt = if entry.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            match reader.read_line(prompt) {
+                Ok(Some(line)) if entry.is_empty() && line.trim_start().starts_with(':') => {
+                    reader.add_history(line.trim());
+                    self.command(&line);
+                    continue;
+                }
+                Ok(Some(line)) => {
+                    entry.push_str(&line);
+                    entry.push('\n');
+                }
+                Ok(None) => {
+                    // whatever was left open is reported rather than dropped
+                    if !entry.trim().is_empty() {
+                        self.eval_line(&entry);
+                    }
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    entry.clear();
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            if is_incomplete(&entry) {
+                continue;
+            }
+            if !entry.trim().is_empty() {
+                reader.add_history(entry.trim_end());
+                self.eval_line(&entry);
+            }
+            entry.clear();
+        }
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::SharedBuffer;
+
+    use super::*;
+
+    fn session(input: &str) -> (String, String) {
+        let mut repl = Repl::new();
+        let (output, diagnostics) = (SharedBuffer::new(), SharedBuffer::new());
+        repl.interpreter().set_output(output.clone());
+        repl.interpreter().set_diagnostics(diagnostics.clone());
+        let mut reader = PlainReader::new(input.as_bytes(), repl.interpreter().output());
+        repl.run(&mut reader).unwrap();
+        (output.contents(), diagnostics.contents())
+    }
+
+    #[test]
+    fn it_keeps_globals_between_lines() {
+        let (output, _) = session("var x = 1;\nx = x + 1;\nprint x;\n");
+        assert_eq!(output, "> > 2\n> 2\n> \n");
+    }
+
+    #[test]
+    fn it_prints_bare_expressions_but_not_nil() {
+        let (output, _) = session("1 + 2;\n\"a\" + \"b\";\nnil;\n");
+        assert_eq!(output, "> 3\n> ab\n> > \n");
+    }
+
+    #[test]
+    fn it_survives_errors() {
+        let (output, diagnostics) = session("var a = 1;\n-\"a\";\nvar b = ;\na;\n");
+        assert_eq!(output, "> > > > 1\n> \n");
+        assert!(diagnostics.contains("Expected number"), "{}", diagnostics);
+        assert!(diagnostics.contains("E0100"), "{}", diagnostics);
+    }
+
+    #[test]
+    fn it_continues_unfinished_entries() {
+        let (output, _) = session("fun add(a, b) {\n  return a + b;\n}\nadd(1,\n 2);\n");
+        assert_eq!(output, "> ... ... > ... 3\n> \n");
+
+        let (output, _) = session("\"multi\nline\";\n");
+        assert_eq!(output, "> ... multi\nline\n> \n");
+    }
+
+    #[test]
+    fn it_detects_incomplete_input() {
+        assert!(is_incomplete("if (a) {"));
+        assert!(is_incomplete("print (1 +"));
+        assert!(is_incomplete("var s = \"abc"));
+        assert!(is_incomplete("/* still commenting"));
+        assert!(!is_incomplete("print 1;"));
+        assert!(!is_incomplete("}"));
+        assert!(!is_incomplete("print \"{\";"));
+    }
+
+    #[test]
+    fn it_reports_input_left_open_at_the_end() {
+        let (_, diagnostics) = session("print (1 +\n");
+        assert!(diagnostics.contains("E0100"), "{}", diagnostics);
+    }
+
+    #[test]
+    fn it_lists_globals() {
+        let (output, _) = session("var b = 2;\nvar a = \"x\";\n:env\n");
+        assert_eq!(output, "> > > a = x\nb = 2\nclock = <native fn>\n> \n");
+    }
+
+    #[test]
+    fn it_resets_the_session() {
+        let (output, diagnostics) = session("var a = 1;\n:reset\na;\n");
+        assert_eq!(output, "> > > > \n");
+        assert!(diagnostics.contains("Undefined variable 'a'"), "{}", diagnostics);
+    }
+
+    #[test]
+    fn it_shows_tokens_and_trees() {
+        let (output, _) = session(":tokens 1 +\n");
+        assert_eq!(output, "> Number(1.0) \"1\" at line 1, column 1\nPlus \"+\" at line 1, column 3\nEOF \"\" at line 1, column 4\n> \n");
+
+        let (output, _) = session(":ast -1\n");
+        assert!(output.starts_with("> Unary("), "{}", output);
+        assert!(output.contains("\n    Literal("), "{}", output);
+    }
+
+    #[test]
+    fn it_loads_and_times_code() {
+        let path = std::env::temp_dir().join("lox_repl_load_test.lox");
+        std::fs::write(&path, "var loaded = 40;").unwrap();
+        let (output, _) = session(&format!(":load {}\n:time loaded + 2;\n", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert!(output.starts_with("> > 42\ntook "), "{}", output);
+    }
+
+    #[test]
+    fn it_times_bare_expressions_but_not_failures() {
+        let (output, _) = session(":time 40 + 2\n");
+        assert!(output.starts_with("> 42\ntook "), "{}", output);
+
+        let (output, diagnostics) = session(":time -\"a\"\n");
+        assert_eq!(output, "> > \n");
+        assert!(diagnostics.contains("Expected number"), "{}", diagnostics);
+    }
+
+    #[test]
+    fn it_rejects_unknown_commands() {
+        let (output, diagnostics) = session(":nope\n:load\n");
+        assert_eq!(output, "> > > \n");
+        assert_eq!(diagnostics, "Unknown command ':nope', try :help\nUsage: :load <file>\n");
+    }
+}
@@ -0,0 +1,324 @@
+use std::{collections::HashMap, fmt::{self, Display}};
+
+use crate::{interner::Symbol, statement::Statement, tokens::Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ResolveError {
+    fn new(message: String, span: Span) -> ResolveError {
+        ResolveError { message, span }
+    }
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Where a local lives at runtime: `index` in the scope `depth` scopes out
+/// from the reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+struct Declaration {
+    // position among the scope's locals, in declaration order
+    index: usize,
+    // false while the initializer is still being resolved
+    defined: bool,
+}
+
+pub trait Resolvable {
+    fn resolve(&mut self, resolver: &mut Resolver);
+}
+
+/// Walks the tree once before it is executed, binding every variable
+/// reference to the slot of its declaration. References that aren't found
+/// in any local scope are left unresolved and looked up in the globals at
+/// runtime.
+pub struct Resolver {
+    scopes: Vec<HashMap<Symbol, Declaration>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    loop_depth: usize,
+    errors: Vec<ResolveError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            scopes: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+            errors: vec![],
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &mut [Box<Statement>]) -> Result<(), Vec<ResolveError>> {
+        for statement in statements.iter_mut() {
+            statement.resolve(self);
+        }
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    pub fn error(&mut self, message: &str, span: Span) {
+        self.errors.push(ResolveError::new(message.to_string(), span));
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    pub fn declare(&mut self, name: Symbol, span: Span) {
+        let duplicate = match self.scopes.last_mut() {
+            Some(scope) => {
+                let index = scope.len();
+                scope.insert(name, Declaration { index, defined: false }).is_some()
+            }
+            None => false,
+        };
+        if duplicate {
+            self.error(&format!("Already a variable named '{}' in this scope.", name), span);
+        }
+    }
+
+    pub fn define(&mut self, name: Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let index = scope.len();
+            scope.entry(name).or_insert(Declaration { index, defined: false }).defined = true;
+        }
+    }
+
+    /// Returns true if `name` has been declared in the innermost scope but its
+    /// initializer is still being resolved.
+    pub fn is_declaring(&self, name: Symbol) -> bool {
+        match self.scopes.last() {
+            Some(scope) => scope.get(&name).is_some_and(|declaration| !declaration.defined),
+            None => false,
+        }
+    }
+
+    /// Where the declaration of `name` lives, or `None` if it must be a
+    /// global.
+    pub fn resolve_local(&self, name: Symbol) -> Option<Slot> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(declaration) = scope.get(&name) {
+                return Some(Slot { depth, index: declaration.index });
+            }
+        }
+        None
+    }
+
+    /// `span` is the whole function declaration, which is where errors about
+    /// its parameters are reported.
+    pub fn resolve_function(&mut self, params: &Vec<Symbol>, body: &mut Statement, function_type: FunctionType, span: Span) {
+        let enclosing_function = self.current_function;
+        let enclosing_loop_depth = self.loop_depth;
+        self.current_function = function_type;
+        self.loop_depth = 0;
+
+        self.begin_scope();
+        for param in params {
+            self.declare(*param, span);
+            self.define(*param);
+        }
+        body.resolve(self);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
+    }
+
+    pub fn in_function(&self) -> bool {
+        self.current_function != FunctionType::None
+    }
+
+    pub fn current_function(&self) -> FunctionType {
+        self.current_function
+    }
+
+    /// Enters a class body, returning the enclosing class type so it can be
+    /// restored with `end_class`.
+    pub fn begin_class(&mut self, class_type: ClassType) -> ClassType {
+        std::mem::replace(&mut self.current_class, class_type)
+    }
+
+    pub fn end_class(&mut self, enclosing: ClassType) {
+        self.current_class = enclosing;
+    }
+
+    pub fn in_class(&self) -> bool {
+        self.current_class != ClassType::None
+    }
+
+    pub fn current_class(&self) -> ClassType {
+        self.current_class
+    }
+
+    pub fn begin_loop(&mut self) {
+        self.loop_depth += 1;
+    }
+
+    pub fn end_loop(&mut self) {
+        self.loop_depth -= 1;
+    }
+
+    pub fn in_loop(&self) -> bool {
+        self.loop_depth > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    use super::*;
+
+    fn resolve(source: &str) -> Result<(), Vec<ResolveError>> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let mut statements = parser.parse().unwrap();
+        Resolver::new().resolve(&mut statements)
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        resolve(source).unwrap_err().into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn it_accepts_valid_programs() {
+        assert_eq!(resolve("var a = 1; { var b = a; fun f() { return b; } while (true) { break; } }"), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_reading_a_local_in_its_own_initializer() {
+        assert_eq!(
+            messages("{ var a = a; }"),
+            vec!["Can't read local variable 'a' in its own initializer."]
+        );
+    }
+
+    #[test]
+    fn it_allows_globals_in_their_own_initializer() {
+        assert_eq!(resolve("var a = 1; var a = a;"), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_duplicate_locals() {
+        assert_eq!(
+            messages("{ var a = 1; var a = 2; }"),
+            vec!["Already a variable named 'a' in this scope."]
+        );
+    }
+
+    #[test]
+    fn it_rejects_top_level_return() {
+        assert_eq!(messages("return 1;"), vec!["Can't return from top-level code."]);
+    }
+
+    #[test]
+    fn it_rejects_break_outside_a_loop() {
+        assert_eq!(messages("break;"), vec!["Can't break outside of a loop."]);
+        assert_eq!(
+            messages("while (true) { fun f() { break; } }"),
+            vec!["Can't break outside of a loop."]
+        );
+    }
+
+    #[test]
+    fn it_rejects_this_outside_a_class() {
+        assert_eq!(messages("print this;"), vec!["Can't use 'this' outside of a class."]);
+        assert_eq!(messages("fun f() { return this; }"), vec!["Can't use 'this' outside of a class."]);
+    }
+
+    #[test]
+    fn it_rejects_returning_a_value_from_an_initializer() {
+        assert_eq!(
+            messages("class A { init() { return 1; } }"),
+            vec!["Can't return a value from an initializer."]
+        );
+        assert_eq!(resolve("class A { init() { return; } }"), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_classes_inheriting_from_themselves() {
+        assert_eq!(messages("class A < A {}"), vec!["A class can't inherit from itself."]);
+    }
+
+    #[test]
+    fn it_rejects_super_outside_a_subclass() {
+        assert_eq!(messages("super.method();"), vec!["Can't use 'super' outside of a class."]);
+        assert_eq!(
+            messages("class A { method() { super.method(); } }"),
+            vec!["Can't use 'super' in a class with no superclass."]
+        );
+        assert_eq!(resolve("class A {} class B < A { method() { super.method(); } }"), Ok(()));
+    }
+
+    #[test]
+    fn it_reports_where_errors_are() {
+        let errors = resolve("fun f() {}\n{\n  var a = 1;\n  var a = 2;\n}").unwrap_err();
+        assert_eq!(errors[0].span, Span::new(28, 38, 4, 3));
+        assert_eq!(errors[0].to_string(), "Already a variable named 'a' in this scope. at line 4, column 3");
+    }
+
+    #[test]
+    fn it_numbers_locals_in_declaration_order() {
+        let (a, b, c) = (Symbol::intern("a"), Symbol::intern("b"), Symbol::intern("c"));
+        let mut resolver = Resolver::new();
+        resolver.begin_scope();
+        resolver.declare(a, Span::new(0, 0, 1, 1));
+        resolver.define(a);
+        resolver.define(b);
+        resolver.begin_scope();
+        resolver.define(c);
+
+        assert_eq!(resolver.resolve_local(c), Some(Slot { depth: 0, index: 0 }));
+        assert_eq!(resolver.resolve_local(b), Some(Slot { depth: 1, index: 1 }));
+        assert_eq!(resolver.resolve_local(a), Some(Slot { depth: 1, index: 0 }));
+        assert_eq!(resolver.resolve_local(Symbol::intern("global")), None);
+    }
+
+    #[test]
+    fn it_reports_every_error() {
+        assert_eq!(messages("break; return;").len(), 2);
+    }
+}
@@ -1,12 +1,27 @@
-use crate::tokens::{Token, TokenType};
-use crate::error::error;
+use crate::diagnostics::{UNEXPECTED_CHARACTER, UNTERMINATED_COMMENT, UNTERMINATED_STRING};
+use crate::tokens::{Span, Token, TokenType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
 
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    errors: Vec<ScanError>,
+    comments: Vec<Span>,
     start: usize,
     current: usize,
     line: usize,
+    // `start` and `current` are byte offsets, always on a char boundary
+    // the column `current` is at, counted in characters as it moves
+    column: usize,
+    // line and column the current lexeme began on
+    start_line: usize,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -14,9 +29,14 @@ impl Scanner {
         Scanner {
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
+            comments: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
@@ -26,12 +46,40 @@ impl Scanner {
 
     fn add_token(&mut self, token_type: TokenType) {
         let text = self.source[self.start..self.current].to_string();
-        self.tokens.push(Token::new(token_type, text, self.line));
+        let span = Span::new(self.start, self.current, self.start_line, self.start_column);
+        self.tokens.push(Token::new(token_type, text, span));
+    }
+
+    // records an error covering the current lexeme and keeps scanning
+    fn error(&mut self, code: &'static str, message: String) {
+        let span = Span::new(self.start, self.current, self.start_line, self.start_column);
+        self.errors.push(ScanError { code, message, span });
+    }
+
+    pub fn errors(&self) -> &Vec<ScanError> {
+        &self.errors
+    }
+
+    /// Where each comment is, delimiters included. Comments aren't tokens,
+    /// so only tools that keep them, like the formatter, ask for these.
+    pub fn comments(&self) -> &Vec<Span> {
+        &self.comments
+    }
+
+    fn add_comment(&mut self) {
+        let span = Span::new(self.start, self.current, self.start_line, self.start_column);
+        self.comments.push(span);
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.column = if c == '\n' { 1 } else { self.column + 1 };
+        c
     }
 
     fn match_char(&mut self, expected: char) -> bool {
@@ -39,28 +87,20 @@ impl Scanner {
             return false;
         }
 
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-
-        self.source.chars().nth(self.current + 1).unwrap()
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     pub fn scan_token(&mut self) {
@@ -110,37 +150,39 @@ impl Scanner {
                     // A comment goes until "*/".
                     while self.peek() != '*' && self.peek_next() != '/' && !self.is_at_end() {
                         if self.peek() == '\n' {
-                            self.line += 1;
+                            self.new_line();
                         }
                         self.advance();
                     }
                     if self.is_at_end() {
-                        error(self.line, "Unterminated comment.".to_string());
+                        self.error(UNTERMINATED_COMMENT, "Unterminated comment.".to_string());
                         return;
                     }
                     // advance past the "*/"
                     self.advance();
                     self.advance();
+                    self.add_comment();
                 } else if self.match_char('/') {
                     // A comment goes until the end of the line.
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.add_comment();
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             }
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => {
                 while self.peek() != '"' && !self.is_at_end() {
                     if self.peek() == '\n' {
-                        self.line += 1;
+                        self.new_line();
                     }
                     self.advance();
                 }
                 if self.is_at_end() {
-                    error(self.line, "Unterminated string.".to_string());
+                    self.error(UNTERMINATED_STRING, "Unterminated string.".to_string());
                     return;
                 }
                 self.advance();
@@ -168,8 +210,8 @@ impl Scanner {
                 self.identifier()
             }
             default => {
-                let msg = format!("Unexpected character. {}", default);
-                error(self.line, msg);
+                let msg = format!("Unexpected character '{}'.", default);
+                self.error(UNEXPECTED_CHARACTER, msg);
             }
         }
     }
@@ -206,11 +248,13 @@ impl Scanner {
         while !self.is_at_end() {
             // we are at the beginning of the next lexeme
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
             self.scan_token();
         }
 
         self.tokens
-            .push(Token::new(TokenType::EOF, "".to_string(), self.line));
+            .push(Token::new(TokenType::EOF, "".to_string(), Span::new(self.current, self.current, self.line, self.column)));
 
         &self.tokens
     }
@@ -218,8 +262,10 @@ impl Scanner {
 
 #[cfg(test)]
 mod tests {
+    use crate::tokens::Span;
+
     fn basic_token(t: super::TokenType) -> super::Token {
-        super::Token::new(t, "".to_string(), 1)
+        super::Token::new(t, "".to_string(), Span::new(20, 20, 1, 21))
     }
 
     #[test]
@@ -228,6 +274,8 @@ mod tests {
         let tokens = scanner.scan_tokens();
         assert_eq!(tokens.len(), 1);
         assert_eq!(*tokens.first().unwrap(), basic_token(super::TokenType::EOF));
+        // but remembers where they were
+        assert_eq!(scanner.comments(), &vec![Span::new(0, 20, 1, 1)]);
     }
 
     #[test]
@@ -240,7 +288,7 @@ mod tests {
             super::Token::new(
                 super::TokenType::String("hello there".to_string()),
                 "\"hello there\"".to_string(),
-                1
+                Span::new(0, 13, 1, 1)
             )
         );
     }
@@ -252,7 +300,61 @@ mod tests {
         assert_eq!(tokens.len(), 2);
         assert_eq!(
             *tokens.first().unwrap(),
-            super::Token::new(super::TokenType::Number(10.69), "10.69".to_string(), 1)
+            super::Token::new(super::TokenType::Number(10.69), "10.69".to_string(), Span::new(0, 5, 1, 1))
+        );
+    }
+
+    #[test]
+    fn it_records_spans() {
+        let mut scanner = super::Scanner::new("var a = 1;\n  print \"x\ny\" + a;".to_string());
+        let tokens = scanner.scan_tokens();
+        let spans: Vec<(String, Span)> = tokens.iter().map(|t| (t.lexeme.clone(), t.span)).collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("var".to_string(), Span::new(0, 3, 1, 1)),
+                ("a".to_string(), Span::new(4, 5, 1, 5)),
+                ("=".to_string(), Span::new(6, 7, 1, 7)),
+                ("1".to_string(), Span::new(8, 9, 1, 9)),
+                (";".to_string(), Span::new(9, 10, 1, 10)),
+                ("print".to_string(), Span::new(13, 18, 2, 3)),
+                ("\"x\ny\"".to_string(), Span::new(19, 24, 2, 9)),
+                ("+".to_string(), Span::new(25, 26, 3, 4)),
+                ("a".to_string(), Span::new(27, 28, 3, 6)),
+                (";".to_string(), Span::new(28, 29, 3, 7)),
+                ("".to_string(), Span::new(29, 29, 3, 8)),
+            ]
         );
     }
+
+    #[test]
+    fn it_collects_errors_and_keeps_scanning() {
+        let mut scanner = super::Scanner::new("var a = @;\nprint \"oops".to_string());
+        let tokens = scanner.scan_tokens().clone();
+        assert_eq!(tokens.len(), 6);
+        let errors = scanner.errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "Unexpected character '@'.");
+        assert_eq!(errors[0].span, Span::new(8, 9, 1, 9));
+        assert_eq!(errors[1].message, "Unterminated string.");
+        assert_eq!(errors[1].span, Span::new(17, 22, 2, 7));
+    }
+
+    #[test]
+    fn it_measures_spans_in_bytes_and_columns_in_characters() {
+        let mut scanner = super::Scanner::new("// café\nprint \"é\"; ü".to_string());
+        let tokens = scanner.scan_tokens().clone();
+        assert_eq!(tokens[1].token_type, super::TokenType::String("é".to_string()));
+        assert_eq!(tokens[1].span, Span::new(15, 19, 2, 7));
+        assert_eq!(tokens[2].span, Span::new(19, 20, 2, 10));
+        assert_eq!(scanner.errors()[0].span, Span::new(21, 23, 2, 12));
+    }
+
+    #[test]
+    fn it_restarts_columns_inside_multiline_lexemes() {
+        let mut scanner = super::Scanner::new("\"a\nbc\" x /* é\n*/ y".to_string());
+        let tokens = scanner.scan_tokens().clone();
+        assert_eq!(tokens[1].span, Span::new(7, 8, 2, 5));
+        assert_eq!(tokens[2].span, Span::new(18, 19, 3, 4));
+    }
 }
@@ -1,12 +1,27 @@
 use crate::{
     environment::{self, Environment},
-    expressions::expressions::{Expression, ExpressionResult, Callable},
+    class::Class,
+    collector::{Collector, Object},
+    expressions::expressions::{Expression, ExpressionResult, Callable, Function},
+    interner::Symbol,
     interpreter::{ErrorType, EvaluationError},
     parser::Literal,
+    resolver::{ClassType, FunctionType, Resolvable, Resolver},
+    tokens::Span,
+    vm::{chunk::OpCode, compiler::{Compilable, Compiler}},
 };
+use std::{collections::HashMap, io::Write, rc::Rc};
 
 pub trait Executable {
-    fn execute(&self, context: &mut Environment) -> Result<(), EvaluationError>;
+    fn execute(&self, context: &mut Environment) -> Result<StatementResult, EvaluationError>;
+}
+
+/// The outcome of executing a statement. `Return` unwinds through enclosing
+/// blocks and loops until it reaches the function call that started them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementResult {
+    None,
+    Return(ExpressionResult),
 }
 
 #[derive(Debug, Clone)]
@@ -19,11 +34,16 @@ pub enum Statement {
     While(WhileStatement),
     Function(FunctionStatement),
     Break(BreakStatement),
+    Return(ReturnStatement),
+    Class(ClassStatement),
 }
 
 impl Statement {
-    pub fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
-        match self {
+    pub fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
+        if let Err(e) = environment.meter().step() {
+            return Err(e.with_span(self.span()));
+        }
+        let result = match self {
             Statement::Expression(statement) => statement.execute(environment),
             Statement::Print(statement) => statement.execute(environment),
             Statement::Var(statement) => statement.execute(environment),
@@ -32,94 +52,222 @@ impl Statement {
             Statement::While(statement) => statement.execute(environment),
             Statement::Function(statement) => statement.execute(environment),
             Statement::Break(statement) => statement.execute(environment),
+            Statement::Return(statement) => statement.execute(environment),
+            Statement::Class(statement) => statement.execute(environment),
+        };
+        result.map_err(|e| e.with_span(self.span()))
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Expression(statement) => statement.span,
+            Statement::Print(statement) => statement.span,
+            Statement::Var(statement) => statement.span,
+            Statement::Block(statement) => statement.span,
+            Statement::If(statement) => statement.span,
+            Statement::While(statement) => statement.span,
+            Statement::Function(statement) => statement.span,
+            Statement::Break(statement) => statement.span,
+            Statement::Return(statement) => statement.span,
+            Statement::Class(statement) => statement.span,
+        }
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) {
+        let enclosing = compiler.set_span(self.span());
+        match self {
+            Statement::Expression(statement) => statement.compile(compiler),
+            Statement::Print(statement) => statement.compile(compiler),
+            Statement::Var(statement) => statement.compile(compiler),
+            Statement::Block(statement) => statement.compile(compiler),
+            Statement::If(statement) => statement.compile(compiler),
+            Statement::While(statement) => statement.compile(compiler),
+            Statement::Function(statement) => statement.compile(compiler),
+            Statement::Break(statement) => statement.compile(compiler),
+            Statement::Return(statement) => statement.compile(compiler),
+            Statement::Class(statement) => statement.compile(compiler),
+        }
+        compiler.set_span(enclosing);
+    }
+
+    pub fn resolve(&mut self, resolver: &mut Resolver) {
+        match self {
+            Statement::Expression(statement) => statement.resolve(resolver),
+            Statement::Print(statement) => statement.resolve(resolver),
+            Statement::Var(statement) => statement.resolve(resolver),
+            Statement::Block(statement) => statement.resolve(resolver),
+            Statement::If(statement) => statement.resolve(resolver),
+            Statement::While(statement) => statement.resolve(resolver),
+            Statement::Function(statement) => statement.resolve(resolver),
+            Statement::Break(statement) => statement.resolve(resolver),
+            Statement::Return(statement) => statement.resolve(resolver),
+            Statement::Class(statement) => statement.resolve(resolver),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct PrintStatement {
-    expression: Box<Expression>,
+    pub expression: Box<Expression>,
+    pub span: Span,
 }
 
 impl PrintStatement {
-    pub fn new(expression: Box<Expression>) -> Self {
-        Self { expression }
+    pub fn new(expression: Box<Expression>, span: Span) -> Self {
+        Self { expression, span }
     }
 }
 
 impl Executable for PrintStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
         let value = self.expression.evaluate(environment)?;
-        println!("{}", value);
-        Ok(())
+        writeln!(environment.output(), "{}", value)
+            .map_err(|e| EvaluationError::runtime_error(format!("Could not write output: {}", e)).with_span(self.span))?;
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for PrintStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        self.expression.compile(compiler);
+        compiler.emit_op(OpCode::Print);
+    }
+}
+
+impl Resolvable for PrintStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        self.expression.resolve(resolver);
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ExpressionStatement {
-    expression: Box<Expression>,
+    pub expression: Box<Expression>,
+    pub span: Span,
 }
 
 impl ExpressionStatement {
-    pub fn new(expression: Box<Expression>) -> Self {
-        Self { expression }
+    pub fn new(expression: Box<Expression>, span: Span) -> Self {
+        Self { expression, span }
     }
 }
 
 impl Executable for ExpressionStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
         self.expression.evaluate(environment)?;
-        Ok(())
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for ExpressionStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        self.expression.compile(compiler);
+        compiler.emit_op(OpCode::Pop);
+    }
+}
+
+impl Resolvable for ExpressionStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        self.expression.resolve(resolver);
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct VarStatement {
-    name: String,
-    initializer: Box<Expression>,
+    pub name: Symbol,
+    pub initializer: Option<Box<Expression>>,
+    pub span: Span,
 }
 
 impl VarStatement {
-    pub fn new(name: String, initializer: Box<Expression>) -> Self {
-        Self { name, initializer }
+    pub fn new(name: Symbol, initializer: Option<Box<Expression>>, span: Span) -> Self {
+        Self { name, initializer, span }
     }
 }
 
 impl Executable for VarStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
-        let value = self.initializer.evaluate(environment)?;
-        // TODO. Assign this value to the global environment.
-        environment.define(self.name.clone(), (value).clone());
-        Ok(())
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
+        // variables declared without an initializer start out as nil
+        let value = match &self.initializer {
+            Some(initializer) => initializer.evaluate(environment)?,
+            None => ExpressionResult::Literal(Literal::Nil),
+        };
+        environment.define(self.name, (value).clone());
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for VarStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        match &self.initializer {
+            Some(initializer) => initializer.compile(compiler),
+            None => compiler.emit_op(OpCode::Nil),
+        }
+        compiler.declare_variable(self.name.as_str());
+        compiler.define_variable(self.name.as_str());
+    }
+}
+
+impl Resolvable for VarStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        resolver.declare(self.name, self.span);
+        if let Some(initializer) = &mut self.initializer {
+            initializer.resolve(resolver);
+        }
+        resolver.define(self.name);
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct BlockStatement {
-    statements: Vec<Box<Statement>>,
+    pub statements: Vec<Box<Statement>>,
+    pub span: Span,
 }
 
 impl BlockStatement {
-    pub fn new(statements: Vec<Box<Statement>>) -> Self {
-        Self { statements }
+    pub fn new(statements: Vec<Box<Statement>>, span: Span) -> Self {
+        Self { statements, span }
     }
 }
 
 impl Executable for BlockStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
         let new_env = &mut environment.enclosed();
         for statement in &self.statements {
-            statement.execute(new_env)?;
+            if let StatementResult::Return(value) = statement.execute(new_env)? {
+                return Ok(StatementResult::Return(value));
+            }
         }
-        Ok(())
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for BlockStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        compiler.begin_scope();
+        for statement in &self.statements {
+            statement.compile(compiler);
+        }
+        compiler.end_scope();
+    }
+}
+
+impl Resolvable for BlockStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        resolver.begin_scope();
+        for statement in &mut self.statements {
+            statement.resolve(resolver);
+        }
+        resolver.end_scope();
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct IfStatement {
-    condition: Box<Expression>,
-    then_branch: Box<Statement>,
-    else_branch: Option<Box<Statement>>,
+    pub condition: Box<Expression>,
+    pub then_branch: Box<Statement>,
+    pub else_branch: Option<Box<Statement>>,
+    pub span: Span,
 }
 
 impl IfStatement {
@@ -127,88 +275,720 @@ impl IfStatement {
         condition: Box<Expression>,
         then_branch: Box<Statement>,
         else_branch: Option<Box<Statement>>,
+        span: Span,
     ) -> Self {
         Self {
             condition,
             then_branch,
             else_branch,
+            span,
         }
     }
 }
 
 impl Executable for IfStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
         let condition = self.condition.evaluate(environment)?;
         if condition.is_truthy() {
-            self.then_branch.execute(environment)?;
+            return self.then_branch.execute(environment);
         } else if let Some(else_branch) = &self.else_branch {
-            else_branch.execute(environment)?;
+            return else_branch.execute(environment);
         }
 
-        Ok(())
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for IfStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        self.condition.compile(compiler);
+        let then_jump = compiler.emit_jump(OpCode::JumpIfFalse);
+        compiler.emit_op(OpCode::Pop);
+        self.then_branch.compile(compiler);
+
+        let else_jump = compiler.emit_jump(OpCode::Jump);
+        compiler.patch_jump(then_jump);
+        compiler.emit_op(OpCode::Pop);
+        if let Some(else_branch) = &self.else_branch {
+            else_branch.compile(compiler);
+        }
+        compiler.patch_jump(else_jump);
+    }
+}
+
+impl Resolvable for IfStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        self.condition.resolve(resolver);
+        self.then_branch.resolve(resolver);
+        if let Some(else_branch) = &mut self.else_branch {
+            else_branch.resolve(resolver);
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct WhileStatement {
-    condition: Box<Expression>,
-    body: Box<Statement>,
+    pub condition: Box<Expression>,
+    pub body: Box<Statement>,
+    pub span: Span,
 }
 
 impl WhileStatement {
-    pub fn new(condition: Box<Expression>, body: Box<Statement>) -> Self {
-        Self { condition, body }
+    pub fn new(condition: Box<Expression>, body: Box<Statement>, span: Span) -> Self {
+        Self { condition, body, span }
     }
 }
 
 impl Executable for WhileStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
         while self.condition.evaluate(environment)?.is_truthy() {
             let r = self.body.execute(environment);
-            if let Err(e) = r {
-                if e.kind == ErrorType::BreakError {
-                    break;
-                } else {
-                    return Err(e);
+            match r {
+                Ok(StatementResult::Return(value)) => return Ok(StatementResult::Return(value)),
+                Ok(StatementResult::None) => {}
+                Err(e) => {
+                    if e.kind == ErrorType::BreakError {
+                        break;
+                    } else {
+                        return Err(e);
+                    }
                 }
             }
         }
-        Ok(())
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for WhileStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        let loop_start = compiler.current_offset();
+        self.condition.compile(compiler);
+        let exit_jump = compiler.emit_jump(OpCode::JumpIfFalse);
+        compiler.emit_op(OpCode::Pop);
+
+        compiler.begin_loop();
+        self.body.compile(compiler);
+        compiler.emit_loop(loop_start);
+
+        compiler.patch_jump(exit_jump);
+        compiler.emit_op(OpCode::Pop);
+        // breaks skip the pop above, since they leave no condition on the stack
+        compiler.end_loop();
+    }
+}
+
+impl Resolvable for WhileStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        self.condition.resolve(resolver);
+        resolver.begin_loop();
+        self.body.resolve(resolver);
+        resolver.end_loop();
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionStatement {
-    name: String,
-    params: Vec<String>,
-    body: Box<Statement>,
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    // shared with every function value made from this declaration
+    pub body: Rc<Statement>,
+    pub span: Span,
 }
 
 impl FunctionStatement {
-    pub fn new(name: String, params: Vec<String>, body: Box<Statement>) -> Self {
-        Self { name, params, body }
+    pub fn new(name: Symbol, params: Vec<Symbol>, body: Box<Statement>, span: Span) -> Self {
+        Self { name, params, body: Rc::from(body), span }
+    }
+
+    fn to_function(&self, closure: Environment, is_initializer: bool) -> Function {
+        Function::new(self.name, self.params.clone(), Rc::clone(&self.body), closure, is_initializer)
     }
 }
 
 impl Executable for FunctionStatement {
-    fn execute(&self, environment: &mut Environment) -> Result<(), EvaluationError> {
-        let function = Callable::UserDefined(self.body.clone(), self.params.clone());
-        environment.define(self.name.clone(), ExpressionResult::Callable(function));
-        Ok(())
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
+        let function = Callable::UserDefined(self.to_function(environment.clone(), false));
+        environment.define(self.name, ExpressionResult::Callable(function));
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for FunctionStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        // declared before the body so the function can refer to itself
+        compiler.declare_variable(self.name.as_str());
+        compiler.function(self.name.as_str(), &self.params, &self.body, FunctionType::Function);
+        compiler.define_variable(self.name.as_str());
+    }
+}
+
+impl Resolvable for FunctionStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        // defined before the body so the function can refer to itself
+        resolver.declare(self.name, self.span);
+        resolver.define(self.name);
+        resolver.resolve_function(&self.params, Rc::make_mut(&mut self.body), FunctionType::Function, self.span);
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct BreakStatement {}
+pub struct BreakStatement {
+    pub span: Span,
+}
 
 impl BreakStatement {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(span: Span) -> Self {
+        Self { span }
     }
 }
 
 impl Executable for BreakStatement {
-    fn execute(&self, _environment: &mut Environment) -> Result<(), EvaluationError> {
-        return Err(EvaluationError::break_error());
+    fn execute(&self, _environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
+        Err(EvaluationError::break_error())
+    }
+}
+
+impl Compilable for BreakStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        compiler.emit_break();
+    }
+}
+
+impl Resolvable for BreakStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        if !resolver.in_loop() {
+            resolver.error("Can't break outside of a loop.", self.span);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReturnStatement {
+    pub value: Option<Box<Expression>>,
+    pub span: Span,
+}
+
+impl ReturnStatement {
+    pub fn new(value: Option<Box<Expression>>, span: Span) -> Self {
+        Self { value, span }
+    }
+}
+
+impl Executable for ReturnStatement {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
+        let value = match &self.value {
+            Some(expression) => expression.evaluate(environment)?,
+            None => ExpressionResult::Literal(Literal::Nil),
+        };
+        Ok(StatementResult::Return(value))
+    }
+}
+
+impl Compilable for ReturnStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        match &self.value {
+            Some(value) => {
+                value.compile(compiler);
+                compiler.emit_op(OpCode::Return);
+            }
+            None => compiler.emit_return(),
+        }
+    }
+}
+
+impl Resolvable for ReturnStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        if !resolver.in_function() {
+            resolver.error("Can't return from top-level code.", self.span);
+        }
+        if let Some(value) = &mut self.value {
+            if resolver.current_function() == FunctionType::Initializer {
+                resolver.error("Can't return a value from an initializer.", self.span);
+            }
+            value.resolve(resolver);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassStatement {
+    pub name: Symbol,
+    pub superclass: Option<Box<Expression>>,
+    pub methods: Vec<FunctionStatement>,
+    pub span: Span,
+}
+
+impl ClassStatement {
+    pub fn new(name: Symbol, superclass: Option<Box<Expression>>, methods: Vec<FunctionStatement>, span: Span) -> Self {
+        Self { name, superclass, methods, span }
+    }
+}
+
+impl Executable for ClassStatement {
+    fn execute(&self, environment: &mut Environment) -> Result<StatementResult, EvaluationError> {
+        let superclass = match &self.superclass {
+            Some(expression) => match expression.evaluate(environment)? {
+                ExpressionResult::Callable(Callable::Class(class)) => Some(class),
+                _ => return Err(EvaluationError::runtime_error("Superclass must be a class.".to_string())),
+            },
+            None => None,
+        };
+
+        // methods of a subclass close over an extra scope that holds `super`
+        let mut method_env = environment.clone();
+        if let Some(superclass) = &superclass {
+            method_env = environment.enclosed();
+            method_env.define(Symbol::intern("super"), ExpressionResult::Callable(Callable::Class(superclass.clone())));
+        }
+
+        let mut methods = HashMap::new();
+        for method in &self.methods {
+            let function = method.to_function(method_env.clone(), method.name.as_str() == "init");
+            methods.insert(method.name, function);
+        }
+        let class = Rc::new(Class::collected(self.name, superclass, methods, environment.collector()));
+        Collector::track(&environment.collector(), Object::Class(class.clone()));
+        environment.define(self.name, ExpressionResult::Callable(Callable::Class(class)));
+        Ok(StatementResult::None)
+    }
+}
+
+impl Compilable for ClassStatement {
+    fn compile(&self, compiler: &mut Compiler) {
+        let name = compiler.identifier_constant(self.name.as_str());
+        compiler.declare_variable(self.name.as_str());
+        compiler.emit_op_short(OpCode::Class, name);
+        compiler.define_variable(self.name.as_str());
+
+        // a subclass's methods capture `super` from a scope of its own, just
+        // like the tree-walker's extra environment
+        if let Some(superclass) = &self.superclass {
+            superclass.compile(compiler);
+            compiler.begin_scope();
+            compiler.declare_variable("super");
+            compiler.get_variable(self.name.as_str());
+            compiler.emit_op(OpCode::Inherit);
+        }
+
+        compiler.get_variable(self.name.as_str());
+        for method in &self.methods {
+            let function_type = if method.name.as_str() == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            let enclosing = compiler.set_span(method.span);
+            compiler.function(method.name.as_str(), &method.params, &method.body, function_type);
+            let name = compiler.identifier_constant(method.name.as_str());
+            compiler.emit_op_short(OpCode::Method, name);
+            compiler.set_span(enclosing);
+        }
+        compiler.emit_op(OpCode::Pop);
+
+        if self.superclass.is_some() {
+            compiler.end_scope();
+        }
+    }
+}
+
+impl Resolvable for ClassStatement {
+    fn resolve(&mut self, resolver: &mut Resolver) {
+        let class_type = if self.superclass.is_some() {
+            ClassType::Subclass
+        } else {
+            ClassType::Class
+        };
+        let enclosing_class = resolver.begin_class(class_type);
+        resolver.declare(self.name, self.span);
+        resolver.define(self.name);
+
+        if let Some(superclass) = &mut self.superclass {
+            if let Expression::Var(var) = superclass.as_ref() {
+                if var.name == self.name {
+                    resolver.error("A class can't inherit from itself.", var.span);
+                }
+            }
+            superclass.resolve(resolver);
+            resolver.begin_scope();
+            resolver.define(Symbol::intern("super"));
+        }
+
+        // methods are bound in a scope of their own that holds `this`
+        resolver.begin_scope();
+        resolver.define(Symbol::intern("this"));
+        for method in &mut self.methods {
+            let function_type = if method.name.as_str() == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            resolver.resolve_function(&method.params, Rc::make_mut(&mut method.body), function_type, method.span);
+        }
+        resolver.end_scope();
+
+        if self.superclass.is_some() {
+            resolver.end_scope();
+        }
+
+        resolver.end_class(enclosing_class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    use super::*;
+
+    fn run(source: &str) -> Environment {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let mut statements = parser.parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        let mut env = Environment::new();
+        for statement in statements {
+            statement.execute(&mut env).unwrap();
+        }
+        env
+    }
+
+    fn run_error(source: &str) -> EvaluationError {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let mut statements = parser.parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        let mut env = Environment::new();
+        for statement in statements {
+            if let Err(e) = statement.execute(&mut env) {
+                return e;
+            }
+        }
+        panic!("expected a runtime error");
+    }
+
+    fn number(n: f64) -> ExpressionResult {
+        ExpressionResult::Literal(Literal::Number(n))
+    }
+
+    #[test]
+    fn it_returns_values_from_functions() {
+        let env = run("fun add(a, b) { return a + b; } var result = add(1, 2);");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(3.0));
+    }
+
+    #[test]
+    fn it_returns_early_from_nested_loops() {
+        let env = run("
+            fun find() {
+                for (var i = 0; i < 10; i = i + 1) {
+                    var j = 0;
+                    while (j < 10) {
+                        if (i * j == 12) {
+                            return i * 100 + j;
+                        }
+                        j = j + 1;
+                    }
+                }
+                return -1;
+            }
+            var result = find();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(206.0));
+    }
+
+    #[test]
+    fn it_returns_early_from_nested_blocks() {
+        let env = run("
+            var reached = false;
+            fun early() {
+                {
+                    {
+                        return 1;
+                    }
+                }
+                reached = true;
+                return 2;
+            }
+            var result = early();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(1.0));
+        assert_eq!(env.get_global(Symbol::intern("reached")).unwrap(), ExpressionResult::Literal(Literal::Boolean(false)));
+    }
+
+    #[test]
+    fn bare_return_yields_no_value() {
+        let env = run("fun nothing() { return; } var result = nothing();");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), ExpressionResult::Literal(Literal::Nil));
+    }
+
+    #[test]
+    fn functions_resolve_names_where_they_are_declared() {
+        let env = run("
+            var x = \"global\";
+            fun read() { return x; }
+            fun shadow() {
+                var x = \"local\";
+                return read();
+            }
+            var result = shadow();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("global"))));
+    }
+
+    #[test]
+    fn closures_keep_their_own_counter() {
+        let env = run("
+            fun make_counter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+            var a = make_counter();
+            var b = make_counter();
+            a();
+            a();
+            var first = a();
+            var second = b();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("first")).unwrap(), number(3.0));
+        assert_eq!(env.get_global(Symbol::intern("second")).unwrap(), number(1.0));
+    }
+
+    #[test]
+    fn callbacks_see_their_factory_arguments() {
+        let env = run("
+            fun adder(n) {
+                fun add(x) { return x + n; }
+                return add;
+            }
+            fun apply(f, n) { return f(n); }
+            var n = 100;
+            var result = apply(adder(5), 1);
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(6.0));
+    }
+
+    #[test]
+    fn recursive_functions_can_call_themselves() {
+        let env = run("
+            fun fib(n) {
+                if (n < 2) { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+            var result = fib(10);
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(55.0));
+    }
+
+    #[test]
+    fn closures_bind_to_the_declaration_in_scope_when_resolved() {
+        let env = run("
+            var a = \"global\";
+            var first = 0;
+            var second = 0;
+            {
+                fun show() { return a; }
+                first = show();
+                var a = \"block\";
+                second = show();
+            }
+        ");
+        assert_eq!(env.get_global(Symbol::intern("first")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("global"))));
+        assert_eq!(env.get_global(Symbol::intern("second")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("global"))));
+    }
+
+    #[test]
+    fn locals_shadow_by_scope_not_by_name() {
+        let env = run("
+            var result;
+            {
+                var a = 1;
+                var b = 2;
+                {
+                    var a = 10;
+                    b = b + a;
+                }
+                result = a + b;
+            }
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(13.0));
+        assert!(env.get_global(Symbol::intern("a")).is_err());
+    }
+
+    #[test]
+    fn instances_hold_fields() {
+        let env = run("
+            class Point {}
+            var p = Point();
+            p.x = 1;
+            p.y = 2;
+            var result = p.x + p.y;
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(3.0));
+    }
+
+    #[test]
+    fn methods_are_bound_to_this() {
+        let env = run("
+            class Counter {
+                init(start) {
+                    this.count = start;
+                }
+                increment() {
+                    this.count = this.count + 1;
+                    return this;
+                }
+            }
+            var counter = Counter(10);
+            var increment = counter.increment;
+            increment();
+            var result = counter.increment().count;
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), number(12.0));
+    }
+
+    #[test]
+    fn initializers_return_the_instance() {
+        let env = run("
+            class Thing {
+                init() {
+                    this.name = \"thing\";
+                    return;
+                }
+            }
+            var thing = Thing();
+            thing.name = \"renamed\";
+            var again = thing.init();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("again")).unwrap(), env.get_global(Symbol::intern("thing")).unwrap());
+    }
+
+    #[test]
+    fn class_calls_check_the_initializer_arity() {
+        let error = run_error("class Pair { init(a, b) {} } Pair(1);");
+        assert_eq!(error.message, "Expected 2 arguments but got 1");
+    }
+
+    #[test]
+    fn undefined_properties_are_runtime_errors() {
+        let error = run_error("class Empty {} Empty().missing;");
+        assert_eq!(error.message, "Undefined property 'missing'.");
+    }
+
+    #[test]
+    fn subclasses_inherit_methods() {
+        let env = run("
+            class Animal {
+                init(name) { this.name = name; }
+                speak() { return this.name + \" makes a sound\"; }
+            }
+            class Dog < Animal {}
+            var result = Dog(\"rex\").speak();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("result")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("rex makes a sound"))));
+    }
+
+    #[test]
+    fn super_calls_dispatch_to_the_superclass() {
+        let env = run("
+            class A {
+                method() { return \"A\"; }
+            }
+            class B < A {
+                method() { return \"B\" + super.method(); }
+                test() { return super.method(); }
+            }
+            class C < B {}
+            var overridden = C().method();
+            var direct = C().test();
+        ");
+        assert_eq!(env.get_global(Symbol::intern("overridden")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("BA"))));
+        assert_eq!(env.get_global(Symbol::intern("direct")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("A"))));
+    }
+
+    #[test]
+    fn inheriting_from_a_non_class_is_a_runtime_error() {
+        let error = run_error("var NotAClass = 1; class Oops < NotAClass {}");
+        assert_eq!(error.message, "Superclass must be a class.");
+    }
+
+    #[test]
+    fn variables_default_to_nil() {
+        let env = run("var a; var is_nil = a == nil;");
+        assert_eq!(env.get_global(Symbol::intern("a")).unwrap(), ExpressionResult::Literal(Literal::Nil));
+        assert_eq!(env.get_global(Symbol::intern("is_nil")).unwrap(), ExpressionResult::Literal(Literal::Boolean(true)));
+    }
+
+    #[test]
+    fn nil_is_falsy() {
+        let env = run("
+            var branch = \"then\";
+            if (nil) { branch = \"then\"; } else { branch = \"else\"; }
+            var negated = !nil;
+        ");
+        assert_eq!(env.get_global(Symbol::intern("branch")).unwrap(), ExpressionResult::Literal(Literal::String(Rc::from("else"))));
+        assert_eq!(env.get_global(Symbol::intern("negated")).unwrap(), ExpressionResult::Literal(Literal::Boolean(true)));
+    }
+
+    #[test]
+    fn equality_works_across_types() {
+        let env = run("
+            var a = nil == nil;
+            var b = nil == false;
+            var c = 1 == \"1\";
+            var d = nil != 0;
+            class Thing {}
+            var thing = Thing();
+            var e = thing == thing;
+            var f = thing == Thing();
+        ");
+        let boolean = |b| ExpressionResult::Literal(Literal::Boolean(b));
+        assert_eq!(env.get_global(Symbol::intern("a")).unwrap(), boolean(true));
+        assert_eq!(env.get_global(Symbol::intern("b")).unwrap(), boolean(false));
+        assert_eq!(env.get_global(Symbol::intern("c")).unwrap(), boolean(false));
+        assert_eq!(env.get_global(Symbol::intern("d")).unwrap(), boolean(true));
+        assert_eq!(env.get_global(Symbol::intern("e")).unwrap(), boolean(true));
+        assert_eq!(env.get_global(Symbol::intern("f")).unwrap(), boolean(false));
+    }
+
+    #[test]
+    fn nil_prints_as_nil() {
+        assert_eq!(format!("{}", ExpressionResult::Literal(Literal::Nil)), "nil");
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_failing_expression() {
+        let error = run_error("var a = 1;\nprint a - \"b\";");
+        assert_eq!(error.span, Some(Span::new(17, 24, 2, 7)));
+        assert_eq!(error.to_string(), "Expected two numbers, got: 1 \"b\" at line 2, column 7");
+    }
+
+    #[test]
+    fn runtime_errors_inside_functions_keep_their_own_span() {
+        let error = run_error("fun f() {\n  return missing;\n}\nf();");
+        assert_eq!(error.span, Some(Span::new(19, 26, 2, 10)));
+    }
+
+    #[test]
+    fn runtime_errors_record_the_calls_they_unwind_through() {
+        let error = run_error("
+            fun inner() {
+                return missing;
+            }
+            fun outer() {
+                return inner();
+            }
+            class Wrapper {
+                init() { outer(); }
+            }
+            Wrapper();
+        ");
+        let functions: Vec<String> = error.trace.iter().map(|frame| frame.function.clone()).collect();
+        assert_eq!(functions, vec!["inner", "outer", "Wrapper"]);
+        let lines: Vec<usize> = error.backtrace().iter().map(|(_, span)| span.unwrap().line).collect();
+        assert_eq!(lines, vec![3, 6, 9, 11]);
     }
 }
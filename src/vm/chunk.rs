@@ -0,0 +1,157 @@
+use crate::tokens::Span;
+
+use super::value::Value;
+
+/// Every instruction is one opcode byte followed by its operands. Constant
+/// indices and jump offsets are two bytes, big-endian; local slots, upvalue
+/// indices and argument counts are one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+}
+
+// indexed by the opcode's byte, so this must stay in declaration order
+const OPCODES: [OpCode; 37] = [
+    OpCode::Constant,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::GetGlobal,
+    OpCode::DefineGlobal,
+    OpCode::SetGlobal,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::SetProperty,
+    OpCode::GetSuper,
+    OpCode::Equal,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Not,
+    OpCode::Negate,
+    OpCode::Print,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::Loop,
+    OpCode::Call,
+    OpCode::Closure,
+    OpCode::CloseUpvalue,
+    OpCode::Return,
+    OpCode::Class,
+    OpCode::Inherit,
+    OpCode::Method,
+];
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+}
+
+/// A function's bytecode, the constants it refers to, and the source span
+/// each byte was compiled from.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    // one entry per byte of `code`, so errors can point back at the source
+    pub spans: Vec<Span>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self { code: vec![], constants: vec![], spans: vec![] }
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        self.spans.push(span);
+    }
+
+    /// Returns the index of the new constant in the pool.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    pub fn line(&self, offset: usize) -> usize {
+        self.spans[offset].line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        for (byte, op) in OPCODES.iter().enumerate() {
+            assert_eq!(*op as u8, byte as u8);
+            assert_eq!(OpCode::from_byte(byte as u8), Some(*op));
+        }
+        assert_eq!(OpCode::from_byte(OPCODES.len() as u8), None);
+    }
+
+    #[test]
+    fn it_tracks_a_span_per_byte() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::number(1.2));
+        chunk.write(OpCode::Constant as u8, Span::new(0, 3, 1, 1));
+        chunk.write(0, Span::new(0, 3, 1, 1));
+        chunk.write(constant as u8, Span::new(0, 3, 1, 1));
+        chunk.write(OpCode::Return as u8, Span::new(4, 10, 2, 1));
+
+        assert_eq!(chunk.read_u16(1), 0);
+        assert_eq!(chunk.line(2), 1);
+        assert_eq!(chunk.line(3), 2);
+    }
+}
@@ -0,0 +1,520 @@
+use std::{collections::HashMap, fmt::{self, Display}};
+
+use crate::{interner::Symbol, resolver::FunctionType, statement::Statement, tokens::Span};
+
+use super::{chunk::{Chunk, OpCode}, heap::{Heap, ObjRef}, value::{FunctionObject, Object, Value, ValueKind}};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+pub trait Compilable {
+    fn compile(&self, compiler: &mut Compiler);
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // captured locals have to be moved off the stack when they go out of scope
+    is_captured: bool,
+}
+
+/// Where a closure finds a captured variable when it is created: either a
+/// local slot of the enclosing function, or one of the enclosing function's
+/// own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+/// Identifies a constant, so each distinct value is added to a chunk once.
+/// Numbers are compared by their bits, so `0` and `-0` stay apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Nil,
+    Boolean(bool),
+    Number(u64),
+    Object(ObjRef),
+}
+
+impl From<Value> for ConstantKey {
+    fn from(value: Value) -> Self {
+        match value.kind() {
+            ValueKind::Nil => ConstantKey::Nil,
+            ValueKind::Boolean(b) => ConstantKey::Boolean(b),
+            ValueKind::Number(n) => ConstantKey::Number(n.to_bits()),
+            ValueKind::Object(object) => ConstantKey::Object(object),
+        }
+    }
+}
+
+struct Loop {
+    scope_depth: usize,
+    // jumps emitted by `break` that still need patching to the loop's end
+    breaks: Vec<usize>,
+}
+
+/// Compilation state for one function. Nested function declarations push a
+/// new one, so the compiler holds a stack of them.
+struct FunctionState {
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    // where each constant already in `chunk` is
+    constants: HashMap<ConstantKey, u16>,
+    // set once the chunk has run out of constants, so that is reported once
+    too_many_constants: bool,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+impl FunctionState {
+    fn new(name: String, function_type: FunctionType) -> Self {
+        // slot 0 holds the function being called, or the receiver in methods
+        let receiver = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            _ => "",
+        };
+        FunctionState {
+            name,
+            arity: 0,
+            chunk: Chunk::new(),
+            constants: HashMap::new(),
+            too_many_constants: false,
+            function_type,
+            locals: vec![Local { name: receiver.to_string(), depth: 0, is_captured: false }],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+/// Compiles a resolved syntax tree into bytecode for the VM. Each node knows
+/// how to compile itself; this holds the state they share and the helpers
+/// for emitting instructions. Constants are allocated on the VM's heap.
+pub struct Compiler<'a> {
+    heap: &'a mut Heap,
+    functions: Vec<FunctionState>,
+    // the span of the node being compiled, recorded against every byte emitted
+    span: Span,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(heap: &'a mut Heap) -> Compiler<'a> {
+        Compiler {
+            heap,
+            functions: vec![FunctionState::new(String::new(), FunctionType::None)],
+            span: Span::new(0, 0, 1, 1),
+            errors: vec![],
+        }
+    }
+
+    /// Compiles a whole program into the function the VM runs as its script.
+    pub fn compile(mut self, statements: &Vec<Box<Statement>>) -> Result<ObjRef, Vec<CompileError>> {
+        for statement in statements {
+            statement.compile(&mut self);
+        }
+        // the implicit return belongs to the end of the program
+        if let Some(last) = statements.last() {
+            self.span = last.span();
+        }
+        self.emit_return();
+
+        if self.errors.is_empty() {
+            let state = self.functions.pop().expect("the script is always being compiled");
+            Ok(self.finish_function(state))
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    pub fn error(&mut self, message: &str) {
+        self.errors.push(CompileError { message: message.to_string(), span: self.span });
+    }
+
+    /// Sets the span recorded for emitted code, returning the previous one so
+    /// it can be restored once the node is done.
+    pub fn set_span(&mut self, span: Span) -> Span {
+        std::mem::replace(&mut self.span, span)
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("the script is always being compiled")
+    }
+
+    pub fn emit_byte(&mut self, byte: u8) {
+        let span = self.span;
+        self.current().chunk.write(byte, span);
+    }
+
+    pub fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    pub fn emit_op_byte(&mut self, op: OpCode, operand: u8) {
+        self.emit_op(op);
+        self.emit_byte(operand);
+    }
+
+    pub fn emit_op_short(&mut self, op: OpCode, operand: u16) {
+        self.emit_op(op);
+        for byte in operand.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    pub fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_op_short(OpCode::Constant, constant);
+    }
+
+    pub fn emit_string(&mut self, s: &str) {
+        let value = self.heap.alloc_string(s.to_string());
+        self.emit_constant(value);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u16 {
+        let key = ConstantKey::from(value);
+        if let Some(&constant) = self.current().constants.get(&key) {
+            return constant;
+        }
+        match u16::try_from(self.current().chunk.constants.len()) {
+            Ok(constant) => {
+                let state = self.current();
+                state.chunk.add_constant(value);
+                state.constants.insert(key, constant);
+                constant
+            }
+            Err(_) => {
+                if !self.current().too_many_constants {
+                    self.current().too_many_constants = true;
+                    self.error("Too many constants in one chunk.");
+                }
+                0
+            }
+        }
+    }
+
+    pub fn identifier_constant(&mut self, name: &str) -> u16 {
+        let value = self.heap.alloc_string(name.to_string());
+        self.make_constant(value)
+    }
+
+    /// Emits a jump with a placeholder offset, returning where to patch it.
+    pub fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op_short(op, u16::MAX);
+        self.current().chunk.code.len() - 2
+    }
+
+    /// Points a jump emitted by `emit_jump` at the next instruction.
+    pub fn patch_jump(&mut self, offset: usize) {
+        let code = &mut self.current().chunk.code;
+        let jump = code.len() - offset - 2;
+        match u16::try_from(jump) {
+            Ok(jump) => code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes()),
+            Err(_) => self.error("Too much code to jump over."),
+        }
+    }
+
+    pub fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        // the offset also skips the two operand bytes themselves
+        let jump = self.current_offset() - loop_start + 2;
+        match u16::try_from(jump) {
+            Ok(jump) => {
+                for byte in jump.to_be_bytes() {
+                    self.emit_byte(byte);
+                }
+            }
+            Err(_) => self.error("Loop body too large."),
+        }
+    }
+
+    pub fn current_offset(&mut self) -> usize {
+        self.current().chunk.code.len()
+    }
+
+    pub fn emit_return(&mut self) {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit_op_byte(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    pub fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.pop() {
+            if local.depth <= depth {
+                self.current().locals.push(local);
+                break;
+            }
+            self.emit_op(if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop });
+        }
+    }
+
+    fn in_global_scope(&mut self) -> bool {
+        self.current().scope_depth == 0
+    }
+
+    /// Reserves the next stack slot for a new local called `name`. Does
+    /// nothing at the top level, where variables are globals instead.
+    pub fn declare_variable(&mut self, name: &str) {
+        if !self.in_global_scope() {
+            self.add_local(name);
+        }
+    }
+
+    /// Binds a declared global to the value on top of the stack. Locals need
+    /// no instruction, since the value is already sitting in their slot.
+    pub fn define_variable(&mut self, name: &str) {
+        if self.in_global_scope() {
+            let constant = self.identifier_constant(name);
+            self.emit_op_short(OpCode::DefineGlobal, constant);
+        }
+    }
+
+    fn add_local(&mut self, name: &str) {
+        if self.current().locals.len() > u8::MAX as usize {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local { name: name.to_string(), depth, is_captured: false });
+    }
+
+    pub fn get_variable(&mut self, name: &str) {
+        let top = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(top, name) {
+            self.emit_op_byte(OpCode::GetLocal, slot);
+        } else if let Some(index) = self.resolve_upvalue(top, name) {
+            self.emit_op_byte(OpCode::GetUpvalue, index);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit_op_short(OpCode::GetGlobal, constant);
+        }
+    }
+
+    pub fn set_variable(&mut self, name: &str) {
+        let top = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(top, name) {
+            self.emit_op_byte(OpCode::SetLocal, slot);
+        } else if let Some(index) = self.resolve_upvalue(top, name) {
+            self.emit_op_byte(OpCode::SetUpvalue, index);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit_op_short(OpCode::SetGlobal, constant);
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        let locals = &self.functions[function].locals;
+        locals.iter().rposition(|local| local.name == name).map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, UpvalueRef { index: slot, is_local: true }));
+        }
+        let index = self.resolve_upvalue(function - 1, name)?;
+        Some(self.add_upvalue(function, UpvalueRef { index, is_local: false }))
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueRef) -> u8 {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+        if upvalues.len() > u8::MAX as usize {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    /// Compiles a function body and emits the instruction that creates a
+    /// closure over it, leaving the closure on the stack.
+    pub fn function(&mut self, name: &str, params: &Vec<Symbol>, body: &Statement, function_type: FunctionType) {
+        let mut state = FunctionState::new(name.to_string(), function_type);
+        state.arity = params.len();
+        self.functions.push(state);
+
+        self.begin_scope();
+        for param in params {
+            self.add_local(param.as_str());
+        }
+        body.compile(self);
+        self.emit_return();
+
+        let state = self.functions.pop().expect("function state was just pushed");
+        let upvalues = state.upvalues.clone();
+        let function = self.finish_function(state);
+
+        let constant = self.make_constant(Value::object(function));
+        self.emit_op_short(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    fn finish_function(&mut self, state: FunctionState) -> ObjRef {
+        let function = FunctionObject::new(state.name, state.arity, state.upvalues.len(), state.chunk);
+        self.heap.alloc(Object::Function(function))
+    }
+
+    pub fn begin_loop(&mut self) {
+        let scope_depth = self.current().scope_depth;
+        self.current().loops.push(Loop { scope_depth, breaks: vec![] });
+    }
+
+    /// Patches every `break` in the loop to jump to the next instruction.
+    pub fn end_loop(&mut self) {
+        let finished = self.current().loops.pop().expect("end_loop without begin_loop");
+        for jump in finished.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    pub fn emit_break(&mut self) {
+        let loop_depth = match self.current().loops.last() {
+            Some(enclosing) => enclosing.scope_depth,
+            None => return self.error("Can't break outside of a loop."),
+        };
+        // discard the locals declared inside the loop without forgetting them,
+        // since the code after the break is still in their scope
+        let ops: Vec<OpCode> = self.current().locals.iter().rev()
+            .take_while(|local| local.depth > loop_depth)
+            .map(|local| if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop })
+            .collect();
+        for op in ops {
+            self.emit_op(op);
+        }
+        let jump = self.emit_jump(OpCode::Jump);
+        self.current().loops.last_mut().expect("checked above").breaks.push(jump);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner, vm::heap::GcConfig};
+
+    use super::*;
+
+    fn compile(source: &str) -> Rc<Chunk> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let mut statements = parser.parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        let mut heap = Heap::new(GcConfig::default());
+        let script = Compiler::new(&mut heap).compile(&statements).unwrap();
+        heap.function(script).chunk.clone()
+    }
+
+    fn ops(chunk: &Chunk) -> Vec<OpCode> {
+        // only valid for chunks without operands wider than the ones listed
+        let code = &chunk.code;
+        let mut ops = vec![];
+        let mut offset = 0;
+        while offset < code.len() {
+            let op = OpCode::from_byte(code[offset]).unwrap();
+            offset += 1 + match op {
+                OpCode::Constant | OpCode::GetGlobal | OpCode::DefineGlobal | OpCode::SetGlobal
+                | OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => 2,
+                OpCode::GetLocal | OpCode::SetLocal | OpCode::Call => 1,
+                _ => 0,
+            };
+            ops.push(op);
+        }
+        ops
+    }
+
+    #[test]
+    fn it_compiles_expressions_to_stack_operations() {
+        let script = compile("print 1 + 2 * 3;");
+        assert_eq!(
+            ops(&script),
+            vec![OpCode::Constant, OpCode::Constant, OpCode::Constant, OpCode::Multiply, OpCode::Add, OpCode::Print, OpCode::Nil, OpCode::Return]
+        );
+        assert_eq!(script.constants, vec![Value::number(1.0), Value::number(2.0), Value::number(3.0)]);
+    }
+
+    #[test]
+    fn it_keeps_locals_in_stack_slots() {
+        let script = compile("var a = 1; { var b = a; b = 2; }");
+        assert_eq!(
+            ops(&script),
+            vec![
+                OpCode::Constant, OpCode::DefineGlobal,
+                OpCode::GetGlobal, OpCode::Constant, OpCode::SetLocal, OpCode::Pop, OpCode::Pop,
+                OpCode::Nil, OpCode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_jumps_out_of_loops_on_break() {
+        let script = compile("while (true) { var a = 1; break; }");
+        assert_eq!(
+            ops(&script),
+            vec![
+                OpCode::True, OpCode::JumpIfFalse, OpCode::Pop,
+                OpCode::Constant, OpCode::Pop, OpCode::Jump, OpCode::Pop,
+                OpCode::Loop, OpCode::Pop,
+                OpCode::Nil, OpCode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_records_the_span_of_each_instruction() {
+        let script = compile("var a = 1;\nprint a;");
+        let print = script.code.iter().position(|byte| *byte == OpCode::Print as u8).unwrap();
+        assert_eq!(script.line(print), 2);
+    }
+
+    #[test]
+    fn it_adds_each_constant_once() {
+        let script = compile("var x = 1; x = x + 1; print \"x\" + \"x\";");
+        // "x" is both the global's name and a string
+        assert_eq!(script.constants.len(), 2);
+    }
+
+    #[test]
+    fn it_reports_running_out_of_constants_once() {
+        let source: String = (0..70_000).map(|n| format!("{};", n)).collect();
+        let mut scanner = Scanner::new(source);
+        let mut statements = Parser::new(scanner.scan_tokens()).parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        let errors = Compiler::new(&mut Heap::new(GcConfig::default())).compile(&statements).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Too many constants in one chunk.");
+    }
+}
@@ -0,0 +1,156 @@
+use super::{chunk::{Chunk, OpCode}, heap::Heap, value::{Object, ValueKind}};
+
+/// Renders a function's bytecode one instruction per line, followed by the
+/// bytecode of every function nested inside it, e.g.
+///
+/// ```text
+/// == <script> ==
+/// 0000    1 Constant          0 '1'
+/// 0003    | DefineGlobal      1 'a'
+/// ```
+pub fn disassemble(heap: &Heap, chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {} ==\n", name);
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(heap, chunk, offset, &mut out);
+    }
+
+    for constant in &chunk.constants {
+        if let ValueKind::Object(object) = constant.kind() {
+            if let Object::Function(function) = heap.get(object) {
+                out.push('\n');
+                out.push_str(&disassemble(heap, &function.chunk, &function.to_string()));
+            }
+        }
+    }
+    out
+}
+
+/// Appends the instruction at `offset` to `out` and returns the offset of
+/// the next one.
+pub fn disassemble_instruction(heap: &Heap, chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    out.push_str(&format!("{:04} ", offset));
+    // only show the line when it changes, like a listing
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        out.push_str(&format!("{:4} ", chunk.line(offset)));
+    }
+
+    let op = match OpCode::from_byte(chunk.code[offset]) {
+        Some(op) => op,
+        None => {
+            out.push_str(&format!("Unknown opcode {}\n", chunk.code[offset]));
+            return offset + 1;
+        }
+    };
+
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => constant_instruction(heap, op, chunk, offset, out),
+        OpCode::GetLocal | OpCode::SetLocal | OpCode::GetUpvalue | OpCode::SetUpvalue | OpCode::Call => {
+            out.push_str(&format!("{:<16} {:4}\n", format!("{:?}", op), chunk.code[offset + 1]));
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse => jump_instruction(op, chunk, offset, true, out),
+        OpCode::Loop => jump_instruction(op, chunk, offset, false, out),
+        OpCode::Closure => closure_instruction(heap, chunk, offset, out),
+        _ => {
+            out.push_str(&format!("{:?}\n", op));
+            offset + 1
+        }
+    }
+}
+
+fn constant_instruction(heap: &Heap, op: OpCode, chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let constant = chunk.read_u16(offset + 1);
+    out.push_str(&format!("{:<16} {:4} '{}'\n", format!("{:?}", op), constant, heap.format(chunk.constants[constant as usize])));
+    offset + 3
+}
+
+fn jump_instruction(op: OpCode, chunk: &Chunk, offset: usize, forward: bool, out: &mut String) -> usize {
+    let jump = chunk.read_u16(offset + 1) as usize;
+    let next = offset + 3;
+    let target = if forward { next + jump } else { next - jump };
+    out.push_str(&format!("{:<16} {:4} -> {:04}\n", format!("{:?}", op), offset, target));
+    next
+}
+
+fn closure_instruction(heap: &Heap, chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let constant = chunk.read_u16(offset + 1);
+    let function = chunk.constants[constant as usize];
+    out.push_str(&format!("{:<16} {:4} '{}'\n", "Closure", constant, heap.format(function)));
+
+    let mut offset = offset + 3;
+    if let ValueKind::Object(function) = function.kind() {
+        for _ in 0..heap.function(function).upvalue_count {
+            let kind = if chunk.code[offset] == 1 { "local" } else { "upvalue" };
+            out.push_str(&format!("{:04}    |                     {} {}\n", offset, kind, chunk.code[offset + 1]));
+            offset += 2;
+        }
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner, vm::{compiler::Compiler, heap::GcConfig}};
+
+    use super::*;
+
+    fn disassemble_source(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let mut statements = parser.parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        let mut heap = Heap::new(GcConfig::default());
+        let script = Compiler::new(&mut heap).compile(&statements).unwrap();
+        let function = heap.function(script);
+        disassemble(&heap, &function.chunk, &function.to_string())
+    }
+
+    #[test]
+    fn it_lists_instructions_with_lines_and_constants() {
+        assert_eq!(
+            disassemble_source("var a = 1;\nprint a + 2;"),
+            "== <script> ==\n\
+             0000    1 Constant            0 '1'\n\
+             0003    | DefineGlobal        1 'a'\n\
+             0006    2 GetGlobal           1 'a'\n\
+             0009    | Constant            2 '2'\n\
+             0012    | Add\n\
+             0013    | Print\n\
+             0014    | Nil\n\
+             0015    | Return\n"
+        );
+    }
+
+    #[test]
+    fn it_resolves_jump_targets() {
+        let listing = disassemble_source("print true and false;");
+        assert!(listing.contains("0001    | JumpIfFalse         1 -> 0006\n"));
+    }
+
+    #[test]
+    fn it_shows_loops_jumping_backwards() {
+        let listing = disassemble_source("while (false) {}");
+        assert!(listing.contains("Loop                5 -> 0000\n"));
+    }
+
+    #[test]
+    fn it_lists_nested_functions_and_their_captures() {
+        let listing = disassemble_source("fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n}");
+        assert!(listing.contains("== <fn outer> ==\n"));
+        assert!(listing.contains("== <fn inner> ==\n"));
+        assert!(listing.contains("Closure             1 '<fn inner>'\n"));
+        assert!(listing.contains("|                     local 1\n"));
+        assert!(listing.contains("GetUpvalue          0\n"));
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+
+use super::value::{ClassObject, Closure, FunctionObject, InstanceObject, Object, Upvalue, Value, ValueKind};
+
+/// A handle to an object on the `Heap`. Handles are only meaningful for the
+/// heap that created them, and only while the object is reachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(super) usize);
+
+/// When the collector runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    // bytes that can be allocated before the first collection
+    pub initial_threshold: usize,
+    // after a collection, the next one happens once the heap has grown this
+    // many times larger than what survived
+    pub growth_factor: usize,
+    // collect on every allocation, to shake out objects that aren't rooted
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig { initial_threshold: 1024 * 1024, growth_factor: 2, stress: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub objects_freed: usize,
+}
+
+struct Entry {
+    object: Object,
+    // what was counted against `bytes_allocated` for this object
+    size: usize,
+    marked: bool,
+}
+
+/// Owns every object the VM creates and frees the ones that are no longer
+/// reachable with a mark-and-sweep collector. Objects can refer to each
+/// other in cycles, which reference counting would leak.
+pub struct Heap {
+    entries: Vec<Option<Entry>>,
+    // slots freed by the last sweep, reused before the heap grows
+    free: Vec<usize>,
+    // every live string, so each distinct text is allocated once and
+    // strings can be compared by handle
+    strings: HashMap<String, ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Heap {
+        Heap {
+            entries: vec![],
+            free: vec![],
+            strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: config.initial_threshold,
+            config,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Strings must go through `alloc_string` instead, so they are interned.
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        let size = object.size();
+        self.bytes_allocated += size;
+        let entry = Some(Entry { object, size, marked: false });
+        match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = entry;
+                ObjRef(index)
+            }
+            None => {
+                self.entries.push(entry);
+                ObjRef(self.entries.len() - 1)
+            }
+        }
+    }
+
+    /// Returns the existing string with the same text if there is one.
+    pub fn alloc_string(&mut self, s: String) -> Value {
+        if let Some(existing) = self.strings.get(&s) {
+            return Value::object(*existing);
+        }
+        let object = self.alloc(Object::String(s.clone()));
+        self.strings.insert(s, object);
+        Value::object(object)
+    }
+
+    /// Looks up an interned string without allocating it.
+    pub fn find_string(&self, s: &str) -> Option<ObjRef> {
+        self.strings.get(s).copied()
+    }
+
+    /// Whether the owner of the roots should call `collect` before its next
+    /// allocation.
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Frees every object not reachable from `roots`. Returns how many
+    /// objects were freed.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Value>) -> usize {
+        let mut gray: Vec<ObjRef> = vec![];
+        for root in roots {
+            self.mark_value(root, &mut gray);
+        }
+        while let Some(object) = gray.pop() {
+            self.blacken(object, &mut gray);
+        }
+        let freed = self.sweep();
+
+        self.next_gc = (self.bytes_allocated * self.config.growth_factor).max(self.config.initial_threshold);
+        self.stats.collections += 1;
+        self.stats.objects_freed += freed;
+        freed
+    }
+
+    fn mark_value(&mut self, value: Value, gray: &mut Vec<ObjRef>) {
+        if let ValueKind::Object(object) = value.kind() {
+            self.mark_object(object, gray);
+        }
+    }
+
+    fn mark_object(&mut self, object: ObjRef, gray: &mut Vec<ObjRef>) {
+        let entry = self.entries[object.0].as_mut().expect("marked a freed object");
+        if !entry.marked {
+            entry.marked = true;
+            gray.push(object);
+        }
+    }
+
+    /// Marks everything `object` refers to.
+    fn blacken(&mut self, object: ObjRef, gray: &mut Vec<ObjRef>) {
+        let mut values: Vec<Value> = vec![];
+        let mut objects: Vec<ObjRef> = vec![];
+        match self.get(object) {
+            Object::String(_) | Object::Native(_) => {}
+            Object::Function(function) => values.extend(function.chunk.constants.iter().copied()),
+            Object::Closure(closure) => {
+                objects.push(closure.function);
+                objects.extend(closure.upvalues.iter().copied());
+            }
+            Object::Upvalue(Upvalue::Closed(value)) => values.push(*value),
+            Object::Upvalue(Upvalue::Open(_)) => {}
+            Object::Class(class) => {
+                // names are keyed by handle, so they must outlive the map
+                objects.extend(class.methods.keys().copied());
+                objects.extend(class.methods.values().copied());
+            }
+            Object::Instance(instance) => {
+                objects.push(instance.class);
+                objects.extend(instance.fields.keys().copied());
+                values.extend(instance.fields.values().copied());
+            }
+            Object::BoundMethod(bound) => {
+                values.push(bound.receiver);
+                objects.push(bound.method);
+            }
+        }
+        for value in values {
+            self.mark_value(value, gray);
+        }
+        for object in objects {
+            self.mark_object(object, gray);
+        }
+    }
+
+    fn sweep(&mut self) -> usize {
+        let mut freed = 0;
+        for (index, slot) in self.entries.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.marked => entry.marked = false,
+                Some(entry) => {
+                    // the intern table doesn't keep strings alive
+                    if let Object::String(s) = &entry.object {
+                        self.strings.remove(s);
+                    }
+                    self.bytes_allocated -= entry.size;
+                    *slot = None;
+                    self.free.push(index);
+                    freed += 1;
+                }
+                None => {}
+            }
+        }
+        freed
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// The number of objects currently allocated, reachable or not.
+    pub fn live_objects(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+
+    pub fn get(&self, object: ObjRef) -> &Object {
+        &self.entries[object.0].as_ref().expect("used a freed object").object
+    }
+
+    pub fn get_mut(&mut self, object: ObjRef) -> &mut Object {
+        &mut self.entries[object.0].as_mut().expect("used a freed object").object
+    }
+
+    pub fn as_string(&self, value: Value) -> Option<&str> {
+        match value.kind() {
+            ValueKind::Object(object) => match self.get(object) {
+                Object::String(s) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn function(&self, object: ObjRef) -> &FunctionObject {
+        match self.get(object) {
+            Object::Function(function) => function,
+            other => panic!("Expected a function, got: {:?}", other),
+        }
+    }
+
+    pub fn closure(&self, object: ObjRef) -> &Closure {
+        match self.get(object) {
+            Object::Closure(closure) => closure,
+            other => panic!("Expected a closure, got: {:?}", other),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, object: ObjRef) -> &mut Upvalue {
+        match self.get_mut(object) {
+            Object::Upvalue(upvalue) => upvalue,
+            other => panic!("Expected an upvalue, got: {:?}", other),
+        }
+    }
+
+    pub fn class(&self, object: ObjRef) -> &ClassObject {
+        match self.get(object) {
+            Object::Class(class) => class,
+            other => panic!("Expected a class, got: {:?}", other),
+        }
+    }
+
+    pub fn class_mut(&mut self, object: ObjRef) -> &mut ClassObject {
+        match self.get_mut(object) {
+            Object::Class(class) => class,
+            other => panic!("Expected a class, got: {:?}", other),
+        }
+    }
+
+    pub fn instance_mut(&mut self, object: ObjRef) -> &mut InstanceObject {
+        match self.get_mut(object) {
+            Object::Instance(instance) => instance,
+            other => panic!("Expected an instance, got: {:?}", other),
+        }
+    }
+
+    /// Same rules as the tree-walker: nil, false, 0 and "" are falsy.
+    pub fn is_truthy(&self, value: Value) -> bool {
+        match value.kind() {
+            ValueKind::Nil => false,
+            ValueKind::Boolean(b) => b,
+            ValueKind::Number(n) => n != 0.0,
+            ValueKind::Object(_) => self.as_string(value) != Some(""),
+        }
+    }
+
+    /// Renders a value the way `print` shows it.
+    pub fn format(&self, value: Value) -> String {
+        match value.kind() {
+            ValueKind::Nil => "nil".to_string(),
+            ValueKind::Boolean(b) => b.to_string(),
+            ValueKind::Number(n) => n.to_string(),
+            ValueKind::Object(object) => match self.get(object) {
+                Object::String(s) => s.clone(),
+                Object::Function(function) => function.to_string(),
+                Object::Closure(closure) => self.function(closure.function).to_string(),
+                Object::Upvalue(_) => "upvalue".to_string(),
+                Object::Native(_) => "<native fn>".to_string(),
+                Object::Class(class) => class.name.clone(),
+                Object::Instance(instance) => format!("{} instance", self.class(instance.class).name),
+                Object::BoundMethod(bound) => self.format(Value::object(bound.method)),
+            },
+        }
+    }
+
+    /// Like `format`, but quotes strings so they stand out in error messages.
+    pub fn describe(&self, value: Value) -> String {
+        match self.as_string(value) {
+            Some(s) => format!("{:?}", s),
+            None => self.format(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heap() -> Heap {
+        Heap::new(GcConfig::default())
+    }
+
+    #[test]
+    fn it_frees_unreachable_objects() {
+        let mut heap = heap();
+        let kept = heap.alloc_string("kept".to_string());
+        heap.alloc_string("garbage".to_string());
+
+        assert_eq!(heap.collect([kept]), 1);
+        assert_eq!(heap.live_objects(), 1);
+        assert_eq!(heap.as_string(kept), Some("kept"));
+    }
+
+    #[test]
+    fn it_traces_through_objects() {
+        let mut heap = heap();
+        let class = heap.alloc(Object::class("Point".to_string()));
+        let instance = heap.alloc(Object::instance(class));
+        let field = heap.alloc_string("field".to_string());
+        let name = heap.alloc_string("name".to_string());
+        heap.instance_mut(instance).fields.insert(name.as_object().unwrap(), field);
+
+        assert_eq!(heap.collect([Value::object(instance)]), 0);
+        assert_eq!(heap.live_objects(), 4);
+    }
+
+    #[test]
+    fn it_frees_cycles() {
+        let mut heap = heap();
+        let class = heap.alloc(Object::class("Node".to_string()));
+        let a = heap.alloc(Object::instance(class));
+        let b = heap.alloc(Object::instance(class));
+        let next = heap.alloc_string("next".to_string()).as_object().unwrap();
+        heap.instance_mut(a).fields.insert(next, Value::object(b));
+        heap.instance_mut(b).fields.insert(next, Value::object(a));
+
+        assert_eq!(heap.collect([]), 4);
+        assert_eq!(heap.live_objects(), 0);
+    }
+
+    #[test]
+    fn it_interns_strings() {
+        let mut heap = heap();
+        let a = heap.alloc_string("name".to_string());
+        let b = heap.alloc_string("name".to_string());
+        assert_eq!(a, b);
+        assert_eq!(heap.live_objects(), 1);
+    }
+
+    #[test]
+    fn it_forgets_interned_strings_once_they_are_freed() {
+        let mut heap = heap();
+        heap.alloc_string("garbage".to_string());
+        heap.collect([]);
+        assert_eq!(heap.find_string("garbage"), None);
+    }
+
+    #[test]
+    fn it_reuses_freed_slots() {
+        let mut heap = heap();
+        heap.alloc_string("garbage".to_string());
+        heap.collect([]);
+        heap.alloc_string("new".to_string());
+        assert_eq!(heap.entries.len(), 1);
+    }
+
+    #[test]
+    fn it_schedules_collections_by_size() {
+        let mut heap = Heap::new(GcConfig { initial_threshold: 200, growth_factor: 2, stress: false });
+        assert!(!heap.should_collect());
+        let big = heap.alloc_string("x".repeat(300));
+        assert!(heap.should_collect());
+
+        heap.collect([big]);
+        assert!(!heap.should_collect());
+        assert_eq!(heap.stats().collections, 1);
+    }
+
+    #[test]
+    fn stress_mode_always_collects() {
+        let heap = Heap::new(GcConfig { stress: true, ..GcConfig::default() });
+        assert!(heap.should_collect());
+    }
+}
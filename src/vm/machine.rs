@@ -0,0 +1,697 @@
+use std::{collections::HashMap, io::Write, rc::Rc};
+
+use crate::{interpreter::EvaluationError, limits::{Limits, Meter}, output::Sink};
+
+use super::{
+    chunk::{Chunk, OpCode},
+    heap::{GcConfig, Heap, ObjRef},
+    value::{Closure, Object, Upvalue, Value, ValueKind},
+};
+
+// frames live on the heap, so this only stops runaway recursion when the
+// call depth isn't otherwise limited
+const FRAMES_MAX: usize = 1 << 20;
+
+struct CallFrame {
+    closure: ObjRef,
+    // the closure's code, kept here so running it doesn't go through the heap
+    chunk: Rc<Chunk>,
+    ip: usize,
+    // index of the frame's slot 0 on the VM stack
+    slots: usize,
+}
+
+/// A stack machine that runs the bytecode produced by `Compiler`.
+pub struct VM {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    // keyed by interned name
+    globals: HashMap<ObjRef, Value>,
+    // upvalues still pointing into the stack, so closures share them
+    open_upvalues: Vec<ObjRef>,
+    // looked up on every instantiation, so interned once up front
+    init_string: ObjRef,
+    // where `print` writes
+    output: Sink,
+    meter: Meter,
+}
+
+fn clock(_args: &[Value]) -> Value {
+    Value::number(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as f64)
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> VM {
+        VM::with_gc(GcConfig::default())
+    }
+
+    pub fn with_gc(config: GcConfig) -> VM {
+        let mut heap = Heap::new(config);
+        let init_string = heap.alloc_string("init".to_string()).as_object().expect("strings are objects");
+        let mut vm = VM {
+            heap,
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            init_string,
+            output: Sink::stdout(),
+            meter: Meter::new(Limits::none()),
+        };
+        vm.set_limits(Limits::default());
+        vm.define_native("clock", 0, clock);
+        vm
+    }
+
+    pub fn define_native(&mut self, name: &str, arity: usize, function: fn(&[Value]) -> Value) {
+        // the name is kept on the stack so allocating the function can't free it
+        let key = self.alloc_string(name.to_string());
+        self.stack.push(key);
+        let native = self.alloc(Object::native(name, arity, function));
+        self.pop();
+        self.globals.insert(key.as_object().expect("strings are objects"), Value::object(native));
+    }
+
+    /// Bounds the work done by every later `interpret`. Exceeding a limit
+    /// raises an error whose kind says which one.
+    /// The call depth is never allowed past `FRAMES_MAX`.
+    pub fn set_limits(&mut self, mut limits: Limits) {
+        let ceiling = FRAMES_MAX - 1;
+        limits.max_call_depth = Some(limits.max_call_depth.map_or(ceiling, |depth| depth.min(ceiling)));
+        self.meter = Meter::new(limits);
+    }
+
+    /// Sends what `print` writes to `writer` instead of stdout.
+    pub fn set_output(&mut self, writer: impl Write + 'static) {
+        self.output.redirect(writer);
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.heap.find_string(name).and_then(|name| self.globals.get(&name).copied())
+    }
+
+    /// The heap scripts are compiled into before they are run.
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Runs a compiled script. Globals it defines stay around for the next one.
+    pub fn interpret(&mut self, script: ObjRef) -> Result<(), EvaluationError> {
+        // the script is only rooted once it is on the stack
+        self.stack.push(Value::object(script));
+        let closure = self.alloc(Object::Closure(Closure { function: script, upvalues: vec![] }));
+        self.stack[0] = Value::object(closure);
+        self.meter.restart();
+        self.call_closure(closure, 0)?;
+
+        let result = self.run();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    /// Allocates through the heap, collecting first if it has grown enough.
+    fn alloc(&mut self, object: Object) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(object)
+    }
+
+    fn alloc_string(&mut self, s: String) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc_string(s)
+    }
+
+    /// Everything the running program can still reach starts from the stack,
+    /// the closures being run, the globals, and variables closures captured.
+    pub fn collect_garbage(&mut self) -> usize {
+        let roots = self.stack.iter().copied()
+            .chain(self.frames.iter().map(|frame| Value::object(frame.closure)))
+            .chain(self.globals.iter().flat_map(|(name, value)| [Value::object(*name), *value]))
+            .chain(self.open_upvalues.iter().map(|upvalue| Value::object(*upvalue)))
+            .chain([Value::object(self.init_string)])
+            .collect::<Vec<Value>>();
+        self.heap.collect(roots)
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("the VM is always running a frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("the VM is always running a frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let short = frame.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        short
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_short() as usize;
+        self.frame().chunk.constants[index]
+    }
+
+    /// Reads a constant holding an interned name.
+    fn read_name(&mut self) -> ObjRef {
+        match self.read_constant().kind() {
+            ValueKind::Object(name) => name,
+            constant => panic!("Expected a name constant, got: {:?}", constant),
+        }
+    }
+
+    fn name(&self, name: ObjRef) -> &str {
+        self.heap.as_string(Value::object(name)).expect("names are strings")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn function_name(&self, closure: ObjRef) -> String {
+        self.heap.function(self.heap.closure(closure).function).name.clone()
+    }
+
+    /// Builds a runtime error pointing at the current instruction, with a
+    /// stack trace of every call still in progress.
+    fn runtime_error(&self, message: String) -> EvaluationError {
+        self.locate(EvaluationError::runtime_error(message))
+    }
+
+    /// Points `error` at the current instruction and adds the stack trace.
+    fn locate(&self, error: EvaluationError) -> EvaluationError {
+        let frame = self.frame();
+        let span = frame.chunk.spans[frame.ip - 1];
+        let mut error = error.with_span(span);
+        for (caller, callee) in self.frames.iter().zip(self.frames.iter().skip(1)).rev() {
+            // the caller's ip is just past its call instruction
+            let call_site = caller.chunk.spans[caller.ip - 1];
+            error = error.in_call(self.function_name(callee.closure), call_site);
+        }
+        error
+    }
+
+    fn run(&mut self) -> Result<(), EvaluationError> {
+        loop {
+            let byte = self.read_byte();
+            if let Err(e) = self.meter.step() {
+                return Err(self.locate(e));
+            }
+            let op = OpCode::from_byte(byte).unwrap_or_else(|| panic!("Unknown opcode {}", byte));
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::NIL),
+                OpCode::True => self.stack.push(Value::boolean(true)),
+                OpCode::False => self.stack.push(Value::boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let value = self.stack[self.frame().slots + slot];
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let index = self.frame().slots + slot;
+                    self.stack[index] = self.peek(0);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(*value),
+                        None => return Err(self.runtime_error(format!("Undefined variable '{}'", self.name(name)))),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek(0);
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(self.runtime_error(format!("Undefined variable '{}'", self.name(name)))),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    let value = match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot],
+                        Upvalue::Closed(value) => *value,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    let value = self.peek(0);
+                    match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let receiver = self.peek(0);
+                    let (class, field) = match receiver.kind() {
+                        ValueKind::Object(object) => match self.heap.get(object) {
+                            Object::Instance(instance) => (instance.class, instance.fields.get(&name).copied()),
+                            _ => return Err(self.runtime_error("Only instances have properties.".to_string())),
+                        },
+                        _ => return Err(self.runtime_error("Only instances have properties.".to_string())),
+                    };
+                    // fields shadow methods
+                    let value = match field {
+                        Some(value) => value,
+                        None => self.bind_method(class, name, receiver)?,
+                    };
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let instance = match self.peek(1).kind() {
+                        ValueKind::Object(object) if matches!(self.heap.get(object), Object::Instance(_)) => object,
+                        _ => return Err(self.runtime_error("Only instances have fields.".to_string())),
+                    };
+                    let value = self.pop();
+                    self.heap.instance_mut(instance).fields.insert(name, value);
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_name();
+                    let superclass = match self.peek(0).kind() {
+                        ValueKind::Object(object) if matches!(self.heap.get(object), Object::Class(_)) => object,
+                        _ => return Err(self.runtime_error("Superclass must be a class.".to_string())),
+                    };
+                    let method = self.bind_method(superclass, name, self.peek(1))?;
+                    self.pop();
+                    self.pop();
+                    self.stack.push(method);
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    // strings are interned, so every object is compared by handle
+                    self.stack.push(Value::boolean(a == b));
+                }
+                OpCode::Greater => self.compare(|a, b| a > b)?,
+                OpCode::GreaterEqual => self.compare(|a, b| a >= b)?,
+                OpCode::Less => self.compare(|a, b| a < b)?,
+                OpCode::LessEqual => self.compare(|a, b| a <= b)?,
+                OpCode::Add => {
+                    let (a, b) = (self.peek(1), self.peek(0));
+                    let result = match (a.kind(), b.kind()) {
+                        (ValueKind::Number(a), ValueKind::Number(b)) => Value::number(a + b),
+                        _ => {
+                            let concatenated = match (self.heap.as_string(a), self.heap.as_string(b), b.kind()) {
+                                (Some(a), Some(b), _) => format!("{}{}", a, b),
+                                (Some(a), None, ValueKind::Number(b)) => format!("{}{}", a, b),
+                                _ => {
+                                    return Err(self.runtime_error(format!(
+                                        "Expected two numbers or two strings, got: {} {}",
+                                        self.heap.describe(a),
+                                        self.heap.describe(b)
+                                    )))
+                                }
+                            };
+                            // the operands stay on the stack until the result is allocated
+                            self.alloc_string(concatenated)
+                        }
+                    };
+                    self.pop();
+                    self.pop();
+                    self.stack.push(result);
+                }
+                OpCode::Subtract => self.arithmetic(|a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic(|a, b| a * b)?,
+                OpCode::Divide => self.arithmetic(|a, b| a / b)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::boolean(!self.heap.is_truthy(value)));
+                }
+                OpCode::Negate => match self.peek(0).kind() {
+                    ValueKind::Number(n) => {
+                        self.pop();
+                        self.stack.push(Value::number(-n));
+                    }
+                    _ => return Err(self.runtime_error(format!("Expected number, got: {}", self.heap.describe(self.peek(0))))),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    let text = self.heap.format(value);
+                    if let Err(e) = writeln!(self.output, "{}", text) {
+                        return Err(self.runtime_error(format!("Could not write output: {}", e)));
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short() as usize;
+                    if !self.heap.is_truthy(self.peek(0)) {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(self.peek(arg_count), arg_count)?;
+                }
+                OpCode::Closure => {
+                    let function = match self.read_constant().kind() {
+                        ValueKind::Object(function) => function,
+                        constant => panic!("Expected a function constant, got: {:?}", constant),
+                    };
+                    let upvalue_count = self.heap.function(function).upvalue_count;
+                    let mut upvalues = Vec::with_capacity(upvalue_count);
+                    for _ in 0..upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        if is_local {
+                            upvalues.push(self.capture_upvalue(self.frame().slots + index));
+                        } else {
+                            upvalues.push(self.heap.closure(self.frame().closure).upvalues[index]);
+                        }
+                    }
+                    let closure = self.alloc(Object::Closure(Closure { function, upvalues }));
+                    self.stack.push(Value::object(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("the VM is always running a frame");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_name();
+                    let class = self.alloc(Object::class(self.name(name).to_string()));
+                    self.stack.push(Value::object(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1).kind() {
+                        ValueKind::Object(object) if matches!(self.heap.get(object), Object::Class(_)) => object,
+                        _ => return Err(self.runtime_error("Superclass must be a class.".to_string())),
+                    };
+                    if let ValueKind::Object(subclass) = self.pop().kind() {
+                        // methods are copied down, so lookups never walk the chain
+                        let inherited = self.heap.class(superclass).methods.clone();
+                        self.heap.class_mut(subclass).methods.extend(inherited);
+                    }
+                }
+                OpCode::Method => {
+                    let name = self.read_name();
+                    if let (ValueKind::Object(class), ValueKind::Object(method)) = (self.peek(1).kind(), self.peek(0).kind()) {
+                        self.heap.class_mut(class).methods.insert(name, method);
+                    }
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, op: fn(f64, f64) -> f64) -> Result<(), EvaluationError> {
+        match (self.peek(1).kind(), self.peek(0).kind()) {
+            (ValueKind::Number(a), ValueKind::Number(b)) => {
+                self.pop();
+                self.pop();
+                self.stack.push(Value::number(op(a, b)));
+                Ok(())
+            }
+            _ => Err(self.runtime_error(format!("Expected two numbers, got: {} {}", self.heap.describe(self.peek(1)), self.heap.describe(self.peek(0))))),
+        }
+    }
+
+    fn compare(&mut self, op: fn(f64, f64) -> bool) -> Result<(), EvaluationError> {
+        match (self.peek(1).kind(), self.peek(0).kind()) {
+            (ValueKind::Number(a), ValueKind::Number(b)) => {
+                self.pop();
+                self.pop();
+                self.stack.push(Value::boolean(op(a, b)));
+                Ok(())
+            }
+            _ => Err(self.runtime_error(format!("Expected two numbers, got: {} {}", self.heap.describe(self.peek(1)), self.heap.describe(self.peek(0))))),
+        }
+    }
+
+    /// `receiver` must still be on the stack, so it survives the allocation.
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef, receiver: Value) -> Result<Value, EvaluationError> {
+        match self.heap.class(class).methods.get(&name).copied() {
+            Some(method) => Ok(Value::object(self.alloc(Object::bound_method(receiver, method)))),
+            None => Err(self.runtime_error(format!("Undefined property '{}'.", self.name(name)))),
+        }
+    }
+
+    /// Calls `callee`, which sits on the stack below its `arg_count` arguments.
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), EvaluationError> {
+        let object = match callee.kind() {
+            ValueKind::Object(object) => object,
+            _ => return Err(self.runtime_error(format!("Can only call functions, not {}", self.heap.describe(callee)))),
+        };
+        let callee_slot = self.stack.len() - arg_count - 1;
+        match self.heap.get(object) {
+            Object::Closure(_) => self.call_closure(object, arg_count),
+            Object::Native(native) => {
+                let (arity, function) = (native.arity, native.function);
+                self.check_arity(arity, arg_count)?;
+                let result = function(&self.stack[callee_slot + 1..]);
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+                Ok(())
+            }
+            Object::Class(class) => {
+                let initializer = class.methods.get(&self.init_string).copied();
+                // the instance takes the class's slot, becoming `this` for `init`
+                let instance = self.alloc(Object::instance(object));
+                self.stack[callee_slot] = Value::object(instance);
+                match initializer {
+                    Some(initializer) => self.call_closure(initializer, arg_count),
+                    None => self.check_arity(0, arg_count),
+                }
+            }
+            Object::BoundMethod(bound) => {
+                let method = bound.method;
+                self.stack[callee_slot] = bound.receiver;
+                self.call_closure(method, arg_count)
+            }
+            _ => Err(self.runtime_error(format!("Can only call functions, not {}", self.heap.describe(callee)))),
+        }
+    }
+
+    fn call_closure(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), EvaluationError> {
+        let function = self.heap.function(self.heap.closure(closure).function);
+        let (arity, chunk) = (function.arity, function.chunk.clone());
+        self.check_arity(arity, arg_count)?;
+        // the script's own frame isn't a call
+        if !self.frames.is_empty() {
+            if let Err(e) = self.meter.check_call_depth(self.frames.len() - 1) {
+                return Err(self.locate(e));
+            }
+        }
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame { closure, chunk, ip: 0, slots });
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: usize, arg_count: usize) -> Result<(), EvaluationError> {
+        if arity != arg_count {
+            return Err(self.runtime_error(format!("Expected {} arguments but got {}", arity, arg_count)));
+        }
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        for upvalue in &self.open_upvalues {
+            if let Object::Upvalue(Upvalue::Open(open)) = self.heap.get(*upvalue) {
+                if *open == slot {
+                    return *upvalue;
+                }
+            }
+        }
+        let upvalue = self.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /// Moves every captured variable at or above `from` off the stack and
+    /// into its upvalue.
+    fn close_upvalues(&mut self, from: usize) {
+        let (heap, stack) = (&mut self.heap, &self.stack);
+        self.open_upvalues.retain(|upvalue| {
+            let upvalue = heap.upvalue_mut(*upvalue);
+            match upvalue {
+                Upvalue::Open(slot) if *slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[*slot]);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::ErrorType, output::SharedBuffer, parser::Parser, resolver::Resolver, scanner::Scanner, vm::compiler::Compiler};
+
+    use super::*;
+
+    fn interpret(vm: &mut VM, source: &str) -> Result<(), EvaluationError> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.scan_tokens());
+        let mut statements = parser.parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        let script = Compiler::new(vm.heap()).compile(&statements).unwrap();
+        vm.interpret(script)
+    }
+
+    #[test]
+    fn globals_outlive_the_script_that_defined_them() {
+        let mut vm = VM::new();
+        interpret(&mut vm, "var a = 1;").unwrap();
+        interpret(&mut vm, "var b = a + 1;").unwrap();
+        assert_eq!(vm.global("b"), Some(Value::number(2.0)));
+    }
+
+    #[test]
+    fn strings_built_at_runtime_equal_their_constants() {
+        let mut vm = VM::new();
+        interpret(&mut vm, "var a = \"a\" + \"b\"; var same = a == \"ab\";").unwrap();
+        assert_eq!(vm.global("same"), Some(Value::boolean(true)));
+    }
+
+    #[test]
+    fn print_writes_to_the_output_sink() {
+        let mut vm = VM::new();
+        let output = SharedBuffer::new();
+        vm.set_output(output.clone());
+        interpret(&mut vm, "print 1 + 2; print \"done\";").unwrap();
+        assert_eq!(output.contents(), "3\ndone\n");
+    }
+
+    #[test]
+    fn runtime_errors_carry_a_stack_trace() {
+        let mut vm = VM::new();
+        let error = interpret(&mut vm, "fun inner() {\n  return -\"a\";\n}\nfun outer() { inner(); }\nouter();").unwrap_err();
+        assert_eq!(error.span.unwrap().line, 2);
+        let functions: Vec<String> = error.trace.iter().map(|frame| frame.function.clone()).collect();
+        assert_eq!(functions, vec!["inner", "outer"]);
+        assert_eq!(error.trace[0].call_site.line, 4);
+        assert_eq!(error.trace[1].call_site.line, 5);
+    }
+
+    #[test]
+    fn limits_raise_their_own_errors() {
+        let mut vm = VM::new();
+        vm.set_limits(Limits { max_steps: Some(1000), ..Limits::none() });
+        let error = interpret(&mut vm, "while (true) {}").unwrap_err();
+        assert_eq!(error.kind, ErrorType::StepLimitExceeded);
+
+        vm.set_limits(Limits { max_call_depth: Some(10), ..Limits::none() });
+        let error = interpret(&mut vm, "fun f(n) { if (n > 0) f(n - 1); } f(9); f(10);").unwrap_err();
+        assert_eq!(error.kind, ErrorType::CallDepthExceeded);
+        assert_eq!(error.span.unwrap().line, 1);
+    }
+
+    #[test]
+    fn unbounded_recursion_overflows_cleanly() {
+        let mut vm = VM::new();
+        let error = interpret(&mut vm, "fun f() { f(); } f();").unwrap_err();
+        assert_eq!(error.kind, ErrorType::CallDepthExceeded);
+
+        // even without limits, frames stop at the ceiling
+        vm.set_limits(Limits::none());
+        let error = interpret(&mut vm, "fun f() { f(); } f();").unwrap_err();
+        assert_eq!(error.message, format!("Stack overflow: exceeded the call depth limit of {}", FRAMES_MAX - 1));
+        // the VM is still usable afterwards
+        interpret(&mut vm, "var ok = true;").unwrap();
+        assert_eq!(vm.global("ok"), Some(Value::boolean(true)));
+    }
+
+    #[test]
+    fn it_collects_cyclic_garbage() {
+        let mut vm = VM::with_gc(GcConfig { initial_threshold: 16 * 1024, ..GcConfig::default() });
+        interpret(&mut vm, "
+            class Node {}
+            for (var i = 0; i < 2000; i = i + 1) {
+                var a = Node();
+                var b = Node();
+                a.next = b;
+                b.next = a;
+                fun recursive() { return recursive; }
+            }
+        ").unwrap();
+        assert!(vm.heap.stats().collections > 0);
+
+        vm.collect_garbage();
+        // only the globals and what they refer to survive
+        assert!(vm.heap.live_objects() < 10, "{} objects survived", vm.heap.live_objects());
+    }
+
+    #[test]
+    fn stress_mode_keeps_everything_reachable_alive() {
+        let mut vm = VM::with_gc(GcConfig { stress: true, ..GcConfig::default() });
+        interpret(&mut vm, "
+            fun makeCounter() {
+                var count = 0;
+                fun increment() { count = count + 1; return count; }
+                return increment;
+            }
+            class Box { init(value) { this.value = value; } get() { return this.value; } }
+            var counter = makeCounter();
+            counter();
+            var box = Box(\"a\" + \"b\");
+            var method = box.get;
+            var result = method() + counter();
+        ").unwrap();
+        let result = vm.global("result").unwrap();
+        assert_eq!(vm.heap.format(result), "ab2");
+        assert!(vm.heap.stats().collections > 10);
+    }
+}
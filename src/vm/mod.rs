@@ -0,0 +1,6 @@
+pub mod chunk;
+pub mod heap;
+pub mod value;
+pub mod compiler;
+pub mod disassembler;
+pub mod machine;
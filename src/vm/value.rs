@@ -0,0 +1,283 @@
+use std::{collections::HashMap, fmt::{self, Display, Formatter}, rc::Rc};
+
+use super::{chunk::Chunk, heap::ObjRef};
+
+/// What a `Value` holds, for matching on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Object(ObjRef),
+}
+
+/// A value on the VM's stack. Anything bigger than a number lives on the
+/// `Heap` and is referred to by handle, so values are always cheap to copy.
+/// By default a value is a tagged enum of 16 bytes; with the `nan-boxing`
+/// feature it is packed into the 8 bytes of an `f64`, see `nan_boxed`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Value(Repr);
+
+#[cfg(not(feature = "nan-boxing"))]
+type Repr = ValueKind;
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub const NIL: Value = Value(ValueKind::Nil);
+
+    pub fn boolean(b: bool) -> Value {
+        Value(ValueKind::Boolean(b))
+    }
+
+    pub fn number(n: f64) -> Value {
+        Value(ValueKind::Number(n))
+    }
+
+    pub fn object(object: ObjRef) -> Value {
+        Value(ValueKind::Object(object))
+    }
+
+    pub fn kind(self) -> ValueKind {
+        self.0
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+type Repr = nan_boxed::Bits;
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    pub const NIL: Value = Value(nan_boxed::NIL);
+
+    pub fn boolean(b: bool) -> Value {
+        Value(if b { nan_boxed::TRUE } else { nan_boxed::FALSE })
+    }
+
+    pub fn number(n: f64) -> Value {
+        Value(nan_boxed::Bits(n.to_bits()))
+    }
+
+    pub fn object(object: ObjRef) -> Value {
+        Value(nan_boxed::object(object))
+    }
+
+    pub fn kind(self) -> ValueKind {
+        nan_boxed::unpack(self.0)
+    }
+}
+
+impl Value {
+    pub fn as_object(self) -> Option<ObjRef> {
+        match self.kind() {
+            ValueKind::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.kind().fmt(f)
+    }
+}
+
+/// Every `f64` that isn't a number is a NaN, and a quiet NaN leaves 51 bits
+/// unused. Values that aren't numbers are stored in those bits: the sign bit
+/// marks a heap handle, otherwise the low bits tag nil, false or true.
+/// Arithmetic only ever produces the canonical NaN, which doesn't have the
+/// extra bit of `QNAN` set and so still reads back as a number.
+#[cfg(feature = "nan-boxing")]
+mod nan_boxed {
+    use super::{ObjRef, ValueKind};
+
+    const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+    const QNAN: u64 = 0x7ffc_0000_0000_0000;
+
+    #[derive(Clone, Copy)]
+    pub struct Bits(pub u64);
+
+    pub const NIL: Bits = Bits(QNAN | 1);
+    pub const FALSE: Bits = Bits(QNAN | 2);
+    pub const TRUE: Bits = Bits(QNAN | 3);
+
+    pub fn object(object: ObjRef) -> Bits {
+        debug_assert!((object.0 as u64) & (SIGN_BIT | QNAN) == 0, "heap index too large to box");
+        Bits(SIGN_BIT | QNAN | object.0 as u64)
+    }
+
+    pub fn unpack(bits: Bits) -> ValueKind {
+        let Bits(bits) = bits;
+        if bits & QNAN != QNAN {
+            ValueKind::Number(f64::from_bits(bits))
+        } else if bits & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN {
+            ValueKind::Object(ObjRef((bits & !(SIGN_BIT | QNAN)) as usize))
+        } else {
+            match bits & 3 {
+                1 => ValueKind::Nil,
+                2 => ValueKind::Boolean(false),
+                _ => ValueKind::Boolean(true),
+            }
+        }
+    }
+
+    // numbers compare as numbers, so NaN != NaN like the enum
+    impl PartialEq for Bits {
+        fn eq(&self, other: &Self) -> bool {
+            match (unpack(*self), unpack(*other)) {
+                (ValueKind::Number(a), ValueKind::Number(b)) => a == b,
+                _ => self.0 == other.0,
+            }
+        }
+    }
+}
+
+/// Everything the heap can hold.
+#[derive(Debug)]
+pub enum Object {
+    String(String),
+    Function(FunctionObject),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Native(NativeFunction),
+    Class(ClassObject),
+    Instance(InstanceObject),
+    BoundMethod(BoundMethod),
+}
+
+impl Object {
+    pub fn native(name: &str, arity: usize, function: fn(&[Value]) -> Value) -> Object {
+        Object::Native(NativeFunction { name: name.to_string(), arity, function })
+    }
+
+    pub fn bound_method(receiver: Value, method: ObjRef) -> Object {
+        Object::BoundMethod(BoundMethod { receiver, method })
+    }
+
+    pub fn class(name: String) -> Object {
+        Object::Class(ClassObject { name, methods: HashMap::new() })
+    }
+
+    pub fn instance(class: ObjRef) -> Object {
+        Object::Instance(InstanceObject { class, fields: HashMap::new() })
+    }
+
+    /// A rough count of the bytes the object keeps alive, used to decide when
+    /// to collect. It only needs to grow with the real number.
+    pub fn size(&self) -> usize {
+        let owned = match self {
+            Object::String(s) => s.len(),
+            Object::Function(function) => function.chunk.code.len() + function.chunk.constants.len() * std::mem::size_of::<Value>(),
+            Object::Closure(closure) => closure.upvalues.len() * std::mem::size_of::<ObjRef>(),
+            Object::Class(class) => class.methods.len() * std::mem::size_of::<(ObjRef, ObjRef)>(),
+            Object::Instance(instance) => instance.fields.len() * std::mem::size_of::<(ObjRef, Value)>(),
+            Object::Upvalue(_) | Object::Native(_) | Object::BoundMethod(_) => 0,
+        };
+        std::mem::size_of::<Object>() + owned
+    }
+}
+
+/// A compiled function: its bytecode plus what the VM needs to call it.
+#[derive(Debug)]
+pub struct FunctionObject {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    // shared with the call frames running it, so the VM can read code
+    // without going through the heap
+    pub chunk: Rc<Chunk>,
+}
+
+impl FunctionObject {
+    pub fn new(name: String, arity: usize, upvalue_count: usize, chunk: Chunk) -> Self {
+        Self { name, arity, upvalue_count, chunk: Rc::new(chunk) }
+    }
+}
+
+impl Display for FunctionObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            return write!(f, "<script>");
+        }
+        write!(f, "<fn {}>", self.name)
+    }
+}
+
+/// A variable captured by a closure. It points into the stack while the
+/// variable is still live there, and holds the value itself once it isn't.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+#[derive(Debug)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: fn(&[Value]) -> Value,
+}
+
+#[derive(Debug)]
+pub struct ClassObject {
+    pub name: String,
+    // keyed by interned name
+    pub methods: HashMap<ObjRef, ObjRef>,
+}
+
+#[derive(Debug)]
+pub struct InstanceObject {
+    pub class: ObjRef,
+    pub fields: HashMap<ObjRef, Value>,
+}
+
+/// A method looked up on an instance, remembering the instance to use as `this`.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip_through_their_representation() {
+        let kinds = [
+            ValueKind::Nil,
+            ValueKind::Boolean(false),
+            ValueKind::Boolean(true),
+            ValueKind::Number(-1.5),
+            ValueKind::Number(f64::INFINITY),
+            ValueKind::Object(ObjRef(42)),
+        ];
+        for kind in kinds {
+            let value = match kind {
+                ValueKind::Nil => Value::NIL,
+                ValueKind::Boolean(b) => Value::boolean(b),
+                ValueKind::Number(n) => Value::number(n),
+                ValueKind::Object(object) => Value::object(object),
+            };
+            assert_eq!(value.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn nan_is_a_number_that_equals_nothing() {
+        let nan = Value::number(f64::NAN);
+        assert!(matches!(nan.kind(), ValueKind::Number(n) if n.is_nan()));
+        assert_ne!(nan, nan);
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    #[test]
+    fn nan_boxed_values_fit_in_a_word() {
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+}
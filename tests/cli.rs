@@ -0,0 +1,135 @@
+//! Runs the `lox-rust` binary the way a user would, checking what it prints
+//! and the exit codes it reports.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn lox(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn it_runs_inline_code_and_stdin() {
+    for backend in ["tree", "vm"] {
+        let output = lox(&["run", "--backend", backend, "-e", "print 1 + 2;"], "");
+        assert_eq!(stdout(&output), "3\n");
+        assert_eq!(output.status.code(), Some(0));
+
+        let output = lox(&["--backend", backend, "-"], "print \"piped\";");
+        assert_eq!(stdout(&output), "piped\n");
+    }
+}
+
+#[test]
+fn errors_exit_like_clox_and_jlox() {
+    for backend in ["tree", "vm"] {
+        let output = lox(&["--backend", backend, "-e", "print (;"], "");
+        assert_eq!(output.status.code(), Some(65));
+        assert!(stderr(&output).contains("E0100"));
+        assert_eq!(stdout(&output), "");
+
+        let output = lox(&["--backend", backend, "-e", "print 1; print -nil; print 2;"], "");
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(stdout(&output), "1\n");
+    }
+
+    assert_eq!(lox(&["--bogus"], "").status.code(), Some(64));
+    assert_eq!(lox(&["missing.lox"], "").status.code(), Some(74));
+}
+
+#[test]
+fn limits_stop_runaway_programs() {
+    for backend in ["tree", "vm"] {
+        let output = lox(&["--backend", backend, "--max-steps", "1000", "-e", "while (true) {}"], "");
+        assert_eq!(output.status.code(), Some(70));
+        assert!(stderr(&output).contains("E0301"), "{}", stderr(&output));
+
+        let output = lox(&["--backend", backend, "--max-depth", "3", "-e", "fun f(n) { if (n > 0) f(n - 1); } f(3);"], "");
+        assert_eq!(output.status.code(), Some(70));
+    }
+}
+
+#[test]
+fn both_backends_recurse_as_deeply() {
+    let program = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(500);";
+    for backend in ["tree", "vm"] {
+        let output = lox(&["--backend", backend, "-e", program], "");
+        assert_eq!(stdout(&output), "500\n", "{}", stderr(&output));
+
+        // a high limit is reached before the host's stack runs out
+        let output = lox(&["--backend", backend, "--max-depth", "100000", "-e", "fun f() { f(); } f();"], "");
+        assert_eq!(output.status.code(), Some(70));
+        assert!(stderr(&output).contains("call depth limit of 100000"), "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn check_reports_errors_without_running() {
+    let output = lox(&["check", "-e", "print \"ran\";"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let output = lox(&["check", "-e", "return 1;"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).contains("E0200"));
+}
+
+#[test]
+fn fmt_prints_the_standard_layout() {
+    let output = lox(&["fmt", "-"], "fun f(a){print a;} // prints\nfor(;;)f(1);");
+    assert_eq!(stdout(&output), "fun f(a) {\n  print a;\n} // prints\n\nfor (;;) f(1);\n");
+}
+
+#[test]
+fn help_is_only_an_option_before_the_source() {
+    let output = lox(&["run", "--help"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Usage:"), "{}", stdout(&output));
+
+    // code and file names that look like the flag
+    let output = lox(&["-e", "-h"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+    let output = lox(&["--", "--help"], "");
+    assert_eq!(output.status.code(), Some(74));
+    assert!(stderr(&output).contains("--help"), "{}", stderr(&output));
+}
+
+#[test]
+fn repl_is_the_default_without_a_source() {
+    let output = lox(&[], "var a = 20;\na + 22;\n");
+    assert_eq!(stdout(&output), "> > 42\n> \n");
+}
+
+#[test]
+fn it_dumps_tokens_and_trees() {
+    let output = lox(&["ast", "-e", "print 1 + 2 * x;"], "");
+    assert_eq!(stdout(&output), "(print (+ 1 (* 2 x)))\n");
+
+    let output = lox(&["tokens", "--format", "sexpr", "-e", "x"], "");
+    assert_eq!(stdout(&output), "(IDENTIFIER \"x\" 1:1)\n(EOF \"\" 1:2)\n");
+
+    let output = lox(&["ast", "--format", "json", "-e", "x;"], "");
+    assert!(stdout(&output).contains("\"type\": \"Variable\""));
+
+    let output = lox(&["ast", "--format", "yaml", "-e", "x;"], "");
+    assert_eq!(output.status.code(), Some(64));
+}
@@ -0,0 +1,185 @@
+//! Drives the interpreter through the library API, the way a host program
+//! embedding Lox would.
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use lox_rust::{output::SharedBuffer, ErrorType, ExpressionResult, FromLox, Interpreter, IntoLox, Limits, Literal};
+
+fn number(n: f64) -> ExpressionResult {
+    ExpressionResult::Literal(Literal::Number(n))
+}
+
+#[test]
+fn eval_returns_the_value_of_a_trailing_expression() {
+    let mut lox = Interpreter::new();
+    assert_eq!(lox.eval("var a = 2; a * 21;").unwrap(), number(42.0));
+    assert_eq!(lox.eval("var b = 1;").unwrap(), ExpressionResult::Literal(Literal::Nil));
+}
+
+#[test]
+fn globals_persist_between_evals() {
+    let mut lox = Interpreter::new();
+    lox.eval("var count = 1;").unwrap();
+    lox.eval("count = count + 1;").unwrap();
+    assert_eq!(lox.get_global("count"), Some(number(2.0)));
+
+    lox.set_global("count", number(10.0));
+    assert_eq!(lox.eval("count;").unwrap(), number(10.0));
+    assert_eq!(lox.get_global("missing"), None);
+}
+
+#[test]
+fn hosts_can_call_lox_functions() {
+    let mut lox = Interpreter::new();
+    lox.eval("fun add(a, b) { return a + b; }").unwrap();
+    assert_eq!(lox.call_function("add", vec![number(1.0), number(2.0)]).unwrap(), number(3.0));
+
+    let error = lox.call_function("add", vec![number(1.0)]).unwrap_err();
+    assert_eq!(error.message, "Expected 2 arguments but got 1");
+    assert!(lox.call_function("missing", vec![]).is_err());
+}
+
+#[test]
+fn lox_can_call_host_closures() {
+    let calls = Rc::new(RefCell::new(vec![]));
+    let mut lox = Interpreter::new();
+    let recorded = calls.clone();
+    lox.define_native("record", 1, move |args| {
+        recorded.borrow_mut().push(args[0].clone());
+        Ok(ExpressionResult::Literal(Literal::Nil))
+    });
+
+    lox.eval("for (var i = 0; i < 3; i = i + 1) record(i);").unwrap();
+    assert_eq!(*calls.borrow(), vec![number(0.0), number(1.0), number(2.0)]);
+
+    let errors = lox.eval("record();").unwrap_err();
+    assert_eq!(errors[0].message, "Expected 1 arguments but got 0");
+}
+
+#[test]
+fn eval_reports_errors_as_diagnostics() {
+    let mut lox = Interpreter::new();
+    let errors = lox.eval("var = 1;").unwrap_err();
+    assert_eq!(errors[0].code, "E0100");
+
+    let errors = lox.eval("1 - \"a\";").unwrap_err();
+    assert_eq!(errors[0].code, "E0300");
+}
+
+#[test]
+fn typed_functions_convert_their_arguments_and_result() {
+    let mut lox = Interpreter::new();
+    lox.define_function("greet", |name: String, times: usize| name.repeat(times));
+    lox.define_function("sum", |numbers: Vec<f64>| numbers.iter().sum::<f64>());
+    lox.define_function("first", |numbers: Vec<f64>| numbers.first().copied());
+    lox.set_global("numbers", vec![1, 2, 3].into_lox());
+
+    assert_eq!(String::from_lox(&lox.eval("greet(\"ab\", 2);").unwrap()).unwrap(), "abab");
+    assert_eq!(f64::from_lox(&lox.eval("sum(numbers);").unwrap()).unwrap(), 6.0);
+    assert_eq!(lox.eval("numbers.length;").unwrap(), number(3.0));
+
+    lox.set_global("empty", Vec::<f64>::new().into_lox());
+    assert_eq!(lox.eval("first(empty);").unwrap(), ExpressionResult::Literal(Literal::Nil));
+}
+
+#[test]
+fn typed_functions_reject_arguments_of_the_wrong_type() {
+    let mut lox = Interpreter::new();
+    lox.define_function("half", |n: i64| -> Result<i64, lox_rust::EvaluationError> {
+        if n % 2 == 0 {
+            Ok(n / 2)
+        } else {
+            Err(lox_rust::EvaluationError::runtime_error(format!("{} is odd", n)))
+        }
+    });
+
+    let error = lox.call_function("half", vec!["four".into_lox()]).unwrap_err();
+    assert_eq!(error.message, "Argument 1 of 'half': Expected number, got string");
+    let error = lox.call_function("half", vec![1.5.into_lox()]).unwrap_err();
+    assert_eq!(error.message, "Argument 1 of 'half': Expected i64, got 1.5");
+    let error = lox.call_function("half", vec![3.into_lox()]).unwrap_err();
+    assert_eq!(error.message, "3 is odd");
+    assert_eq!(lox.call_function("half", vec![4.into_lox()]).unwrap(), number(2.0));
+}
+
+#[test]
+fn output_and_diagnostics_go_to_their_own_sinks() {
+    let mut lox = Interpreter::new();
+    let output = SharedBuffer::new();
+    let diagnostics = SharedBuffer::new();
+    lox.set_output(output.clone());
+    lox.set_diagnostics(diagnostics.clone());
+
+    // functions defined before the redirect print to the new sink too
+    lox.eval("fun show(x) { print x; }").unwrap();
+    lox.eval("show(\"hi\"); print 1 + 1;").unwrap();
+    assert_eq!(output.contents(), "hi\n2\n");
+
+    let source = "print 1 - \"a\";";
+    for diagnostic in lox.eval(source).unwrap_err() {
+        lox.report("<test>", source, &diagnostic);
+    }
+    assert_eq!(output.contents(), "hi\n2\n");
+    assert!(diagnostics.contents().contains("<test>:1"), "{}", diagnostics.contents());
+}
+
+#[test]
+fn runaway_loops_hit_the_step_limit() {
+    let mut lox = Interpreter::new();
+    lox.set_limits(Limits { max_steps: Some(10_000), ..Limits::none() });
+    let error = lox.execute(lox_rust::parse("while (true) {}").unwrap()).unwrap_err();
+    assert_eq!(error.kind, ErrorType::StepLimitExceeded);
+
+    // the budget is per run, and the interpreter is still usable
+    assert_eq!(lox.eval("1 + 1;").unwrap(), number(2.0));
+}
+
+#[test]
+fn deep_recursion_hits_the_call_depth_limit() {
+    let mut lox = Interpreter::new();
+    lox.eval("fun down(n) { if (n > 0) down(n - 1); }").unwrap();
+    assert!(lox.call_function("down", vec![100.into_lox()]).is_ok());
+
+    let error = lox.call_function("down", vec![100_000.into_lox()]).unwrap_err();
+    assert_eq!(error.kind, ErrorType::CallDepthExceeded);
+
+    lox.set_limits(Limits { max_call_depth: Some(5), ..Limits::none() });
+    assert!(lox.call_function("down", vec![4.into_lox()]).is_ok());
+    let error = lox.call_function("down", vec![5.into_lox()]).unwrap_err();
+    assert_eq!(error.kind, ErrorType::CallDepthExceeded);
+
+    // the call that goes too deep is the caller's error
+    let program = lox_rust::parse("fun f(n) { if (n > 0) f(n - 1); } fun g() { f(10); } g();").unwrap();
+    let error = lox.execute(program).unwrap_err();
+    let frames: Vec<String> = error.backtrace().into_iter().map(|(function, _)| function).collect();
+    assert_eq!(frames, vec!["f", "f", "f", "f", "g", "<script>"]);
+
+    // the stack grows as needed, even on a test's small thread
+    lox.set_limits(Limits::none());
+    assert!(lox.call_function("down", vec![50_000.into_lox()]).is_ok());
+}
+
+#[test]
+fn long_runs_time_out() {
+    let mut lox = Interpreter::new();
+    lox.set_limits(Limits { timeout: Some(Duration::from_millis(20)), ..Limits::none() });
+    let error = lox.execute(lox_rust::parse("while (true) {}").unwrap()).unwrap_err();
+    assert_eq!(error.kind, ErrorType::Timeout);
+
+    let diagnostic = lox_rust::Diagnostic::from(&error);
+    assert_eq!(diagnostic.code, lox_rust::diagnostics::LIMIT_EXCEEDED);
+}
+
+#[test]
+fn functions_outlive_the_interpreter_that_made_them() {
+    let mut lox = Interpreter::new();
+    lox.eval("var base = 40; fun add(n) { return base + n; }").unwrap();
+    let add = lox.get_global("add").unwrap();
+    lox.reset();
+    drop(lox);
+
+    match add {
+        ExpressionResult::Callable(add) => assert_eq!(add.call(vec![2.into_lox()]).unwrap(), number(42.0)),
+        other => panic!("Expected a function, got {:?}", other),
+    }
+}
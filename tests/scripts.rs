@@ -0,0 +1,69 @@
+//! Runs every script in `tests/scripts` on each backend and checks its
+//! output against the `// expect: ` comments in the script, and the error
+//! it stops with against a `// expect runtime error: ` comment.
+
+use std::{fs, path::Path, process::Command};
+
+fn expected_output(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .filter_map(|line| line.split_once("// expect: "))
+        .map(|(_, expected)| expected.to_string())
+        .collect()
+}
+
+fn expected_error(source: &str) -> Option<String> {
+    source
+        .lines()
+        .find_map(|line| line.split_once("// expect runtime error: "))
+        .map(|(_, expected)| expected.to_string())
+}
+
+fn run_scripts(args: &[&str]) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let mut scripts: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    for script in scripts {
+        let source = fs::read_to_string(&script).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+            .args(args)
+            .arg(&script)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let actual: Vec<&str> = stdout.lines().collect();
+        assert_eq!(actual, expected_output(&source), "{} with {:?}", script.display(), args);
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let first_line = stderr.lines().next().unwrap_or("");
+        match expected_error(&source) {
+            Some(message) => {
+                assert_eq!(first_line.split_once("]: ").map(|(_, m)| m), Some(message.as_str()), "{} with {:?}", script.display(), args);
+                assert_eq!(output.status.code(), Some(70), "{} with {:?}", script.display(), args);
+            }
+            None => assert!(output.status.success(), "{} with {:?}: {}", script.display(), args, stderr),
+        }
+    }
+}
+
+#[test]
+fn scripts_pass_on_the_tree_walker() {
+    run_scripts(&["--backend", "tree"]);
+}
+
+#[test]
+fn scripts_pass_on_the_tree_walker_collecting_on_every_allocation() {
+    run_scripts(&["--backend", "tree", "--gc-stress"]);
+}
+
+#[test]
+fn scripts_pass_on_the_vm() {
+    run_scripts(&["--backend", "vm"]);
+}
+
+#[test]
+fn scripts_pass_on_the_vm_collecting_on_every_allocation() {
+    run_scripts(&["--backend", "vm", "--gc-stress"]);
+}